@@ -0,0 +1,68 @@
+/*
+This is an example to measure the performance of sorting deep trees,
+where the solving phase needs many passes.
+
+Run with `cargo run --release --example deep`.
+*/
+
+extern crate tree_mem_sort;
+
+use std::time::Instant;
+
+use tree_mem_sort::sort_counting;
+
+#[derive(Clone, PartialEq, Debug)]
+struct Node {
+    val: u32,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Builds a chain with `n` nodes, where node `k` in the chain is stored at position `pos[k]`.
+fn chain(pos: &[usize]) -> Vec<Node> {
+    let n = pos.len();
+    let mut nodes: Vec<Node> = (0..n).map(|_| Node {
+        val: 0,
+        parent: None,
+        children: vec![],
+    }).collect();
+    for k in 0..n {
+        nodes[pos[k]].val = k as u32;
+        if k > 0 {
+            nodes[pos[k]].parent = Some(pos[k - 1]);
+            nodes[pos[k - 1]].children.push(pos[k]);
+        }
+    }
+    nodes
+}
+
+fn main() {
+    for &n in &[1_000, 10_000, 30_000] {
+        // A sorted chain where every hundredth node is moved to the end.
+        let mut pos: Vec<usize> = (0..n).collect();
+        let mut moved: Vec<usize> = (0..n).filter(|k| k % 100 == 50).collect();
+        pos.retain(|k| k % 100 != 50);
+        pos.append(&mut moved);
+        let mut inv = vec![0; n];
+        for (i, &k) in pos.iter().enumerate() {
+            inv[k] = i;
+        }
+        // A sorted chain where the leaf is moved to the front,
+        // which moves the leaf one step per pass.
+        let leaf_first: Vec<usize> = (0..n).map(|k| if k == n - 1 {0} else {k + 1}).collect();
+        let layouts = [
+            ("mostly sorted", inv),
+            ("leaf first", leaf_first),
+            ("reversed", (0..n).rev().collect()),
+        ];
+
+        for (name, pos) in layouts.iter() {
+            let mut nodes = chain(pos);
+            let start = Instant::now();
+            let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            let elapsed = start.elapsed();
+            assert!(nodes.iter().enumerate().all(|(i, n)| n.val == i as u32));
+            println!("{:>6} nodes, {:>13}: {:>6} passes, {:?}", n, name, stats.passes, elapsed);
+        }
+    }
+}
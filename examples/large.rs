@@ -0,0 +1,64 @@
+/*
+This is an example to compare `sort` and `sort_large` on nodes with a large payload,
+stored in a scrambled order.
+
+Run with `cargo run --release --example large`.
+*/
+
+extern crate tree_mem_sort;
+
+use std::time::Instant;
+
+use tree_mem_sort::{sort, sort_large};
+
+#[derive(Clone, PartialEq, Debug)]
+struct Node {
+    payload: [u64; 32],
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Builds a tree with `n` nodes, where every node `k > 0` has the parent `(k - 1) / 4`,
+/// stored in a scrambled order.
+fn scrambled_tree(n: usize) -> Vec<Node> {
+    let mut pos: Vec<usize> = (0..n).collect();
+    let mut seed: u64 = 1;
+    for i in (1..n).rev() {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        pos.swap(i, (seed >> 33) as usize % (i + 1));
+    }
+
+    let mut nodes: Vec<Node> = (0..n).map(|_| Node {
+        payload: [0; 32],
+        parent: None,
+        children: vec![],
+    }).collect();
+    for k in 0..n {
+        nodes[pos[k]].payload[0] = k as u64;
+        if k > 0 {
+            let p = (k - 1) / 4;
+            nodes[pos[k]].parent = Some(pos[p]);
+            nodes[pos[p]].children.push(pos[k]);
+        }
+    }
+    nodes
+}
+
+fn main() {
+    for &n in &[100_000, 1_000_000] {
+        let nodes = scrambled_tree(n);
+
+        let mut a = nodes.clone();
+        let start = Instant::now();
+        sort(&mut a, |n| &mut n.parent, |n| &mut n.children);
+        let elapsed_sort = start.elapsed();
+
+        let mut b = nodes;
+        let start = Instant::now();
+        sort_large(&mut b, |n| &mut n.parent, |n| &mut n.children);
+        let elapsed_large = start.elapsed();
+
+        assert!(a == b);
+        println!("{:>8} nodes: sort {:?}, sort_large {:?}", n, elapsed_sort, elapsed_large);
+    }
+}
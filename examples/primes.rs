@@ -73,16 +73,16 @@ fn main() {
             children: vec![],
         }, // 4
     ];
-    for i in 0..nodes.len() {
-        println!("{}: {:?}", i, nodes[i]);
+    for (i, node) in nodes.iter().enumerate() {
+        println!("{}: {:?}", i, node);
     }
     // Prints `[2, 6, 2, 12, 3]`
     println!("{:?}", nodes.iter().map(|n| n.value).collect::<Vec<u32>>());
 
     sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-    println!("");
-    for i in 0..nodes.len() {
-        println!("{}: {:?}", i, nodes[i]);
+    println!();
+    for (i, node) in nodes.iter().enumerate() {
+        println!("{}: {:?}", i, node);
     }
     // Prints `[12, 2, 6, 3, 2]`
     println!("{:?}", nodes.iter().map(|n| n.value).collect::<Vec<u32>>());
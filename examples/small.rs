@@ -0,0 +1,65 @@
+/*
+This is an example to measure the performance of sorting many small trees,
+comparing `sort` that keeps the generator on the stack for small trees,
+with allocating a new generator for every sort.
+
+Run with `cargo run --release --example small`.
+*/
+
+extern crate tree_mem_sort;
+
+use std::time::Instant;
+
+use tree_mem_sort::{sort, sort_with_scratch};
+
+#[derive(Clone, PartialEq, Debug)]
+struct Node {
+    val: u32,
+    parent: Option<usize>,
+    children: [usize; 2],
+    len: usize,
+}
+
+/// Builds a binary tree with `n` nodes stored in reverse breadth first order.
+fn small_tree(n: usize) -> Vec<Node> {
+    let mut nodes: Vec<Node> = (0..n).map(|k| Node {
+        val: k as u32,
+        parent: None,
+        children: [0; 2],
+        len: 0,
+    }).collect();
+    for k in 1..n {
+        let (i, p) = (n - 1 - k, n - 1 - (k - 1) / 2);
+        nodes[i].parent = Some(p);
+        let len = nodes[p].len;
+        nodes[p].children[len] = i;
+        nodes[p].len += 1;
+    }
+    nodes
+}
+
+fn main() {
+    let sorts = 1_000_000;
+    for &n in &[4, 16, 32] {
+        let tree = small_tree(n);
+        let mut nodes = tree.clone();
+
+        let start = Instant::now();
+        for _ in 0..sorts {
+            nodes.clone_from(&tree);
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children[..n.len]);
+        }
+        let elapsed_stack = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..sorts {
+            nodes.clone_from(&tree);
+            sort_with_scratch(&mut nodes, |n| &mut n.parent, |n| &mut n.children[..n.len],
+                &mut Vec::new());
+        }
+        let elapsed_heap = start.elapsed();
+
+        println!("{} sorts of {:>2} nodes: stack {:?}, heap {:?}",
+            sorts, n, elapsed_stack, elapsed_heap);
+    }
+}
@@ -0,0 +1,67 @@
+/*
+This is an example to measure the performance of sorting a tree with wide fanout,
+where the root and some of its children have many children each.
+
+Run with `cargo run --release --example wide`.
+*/
+
+extern crate tree_mem_sort;
+
+use std::time::Instant;
+
+use tree_mem_sort::sort_counting;
+
+#[derive(PartialEq, Debug)]
+struct Node {
+    val: u32,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Builds a tree with `fanout` children of the root and `fanout` children of every
+/// tenth child, stored in a scrambled order.
+fn wide_tree(fanout: usize) -> Vec<Node> {
+    // Node `k` in breadth-first order is stored at position `pos[k]`.
+    let mut parents: Vec<Option<usize>> = vec![None];
+    for k in 0..fanout {
+        parents.push(Some(0));
+        if k % 10 == 0 {
+            for _ in 0..fanout {
+                parents.push(Some(k + 1));
+            }
+        }
+    }
+    let n = parents.len();
+    let mut pos: Vec<usize> = (0..n).collect();
+    let mut seed: u64 = 1;
+    for i in (1..n).rev() {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        pos.swap(i, (seed >> 33) as usize % (i + 1));
+    }
+
+    let mut nodes: Vec<Node> = (0..n).map(|_| Node {
+        val: 0,
+        parent: None,
+        children: vec![],
+    }).collect();
+    for (k, p) in parents.iter().enumerate() {
+        let node = &mut nodes[pos[k]];
+        node.val = k as u32;
+        node.parent = p.map(|p| pos[p]);
+        if let Some(p) = *p {
+            nodes[pos[p]].children.push(pos[k]);
+        }
+    }
+    nodes
+}
+
+fn main() {
+    for &fanout in &[100, 300, 1000] {
+        let mut nodes = wide_tree(fanout);
+        let start = Instant::now();
+        let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        let elapsed = start.elapsed();
+        println!("fanout {:>4}: {} nodes, {} passes, {} generator swaps, {:?}",
+            fanout, nodes.len(), stats.passes, stats.generator_swaps, elapsed);
+    }
+}
@@ -0,0 +1,215 @@
+//! Sorting trees that are accessed by index, independent of how they are stored.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{retrace, visit, SortOrder};
+
+/// A tree that is accessed by node index, such as a tree stored as a structure of arrays.
+///
+/// The children of a node do not need to be stored as a slice,
+/// and the node data does not need to be stored in a single array.
+pub trait IndexedTree {
+    /// Returns the number of nodes.
+    fn len(&self) -> usize;
+    /// Returns `true` if the tree has no nodes.
+    fn is_empty(&self) -> bool {self.len() == 0}
+    /// Returns the parent of node `i`.
+    fn parent(&self, i: usize) -> Option<usize>;
+    /// Sets the parent of node `i`.
+    fn set_parent(&mut self, i: usize, parent: Option<usize>);
+    /// Returns the number of children of node `i`.
+    fn num_children(&self, i: usize) -> usize;
+    /// Returns child `k` of node `i`.
+    fn child(&self, i: usize, k: usize) -> usize;
+    /// Sets child `k` of node `i`.
+    fn set_child(&mut self, i: usize, k: usize, child: usize);
+    /// Swaps all data of nodes `i` and `j`, including parents and children.
+    fn swap(&mut self, i: usize, j: usize);
+}
+
+/// The same algorithm as `sort`, but for a tree accessed through `IndexedTree`.
+///
+/// This decouples the algorithm from the layout of the children and node data.
+pub fn sort_indexed<T: IndexedTree>(tree: &mut T) {
+    let n = tree.len();
+    let mut gen: Vec<usize> = (0..n).collect();
+    // Collect the children of each node once per pass.
+    let mut buf: Vec<usize> = vec![];
+    let mut scratch = vec![];
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for i in 0..n {
+            buf.clear();
+            buf.extend((0..tree.num_children(i)).map(|k| tree.child(i, k)));
+            changed |= visit(i, &buf, &mut gen, SortOrder::Ascending, &mut scratch) > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+
+    for i in 0..n {
+        let p = tree.parent(i).map(|p| gen[p]);
+        tree.set_parent(i, p);
+        for k in 0..tree.num_children(i) {
+            let ch = gen[tree.child(i, k)];
+            tree.set_child(i, k, ch);
+        }
+    }
+
+    // Retrace the swaps, see `retrace`.
+    for i in 0..n {
+        while gen[i] != i {
+            let j = gen[i];
+            tree.swap(i, j);
+            gen.swap(i, j);
+        }
+    }
+}
+
+/// The same algorithm as `sort`, but for nodes that refer to their children by keys.
+///
+/// The child in slot `k` of a node is stored at `resolve(node, k)`,
+/// for example by looking up the key of the child in a map from keys to positions.
+/// After solving, `update(node, k, new)` is called for every child slot
+/// with the position where that child is moved, before the nodes are moved.
+/// The parents are not used, so parents that are stored by key need no update.
+///
+/// Returns where every node moved, see `sort_with_map`,
+/// which can be used to update a map from keys to positions.
+pub fn sort_keyed<T, N, R, U>(nodes: &mut [T], num_children: N, resolve: R, mut update: U)
+    -> Vec<usize>
+    where N: Fn(&T) -> usize,
+          R: Fn(&T, usize) -> usize,
+          U: FnMut(&mut T, usize, usize)
+{
+    let n = nodes.len();
+    let mut gen: Vec<usize> = (0..n).collect();
+    // Resolve the children of each node once per pass.
+    let mut buf: Vec<usize> = vec![];
+    let mut scratch = vec![];
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for (i, node) in nodes.iter().enumerate() {
+            buf.clear();
+            buf.extend((0..num_children(node)).map(|k| resolve(node, k)));
+            changed |= visit(i, &buf, &mut gen, SortOrder::Ascending, &mut scratch) > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return gen;
+    }
+
+    for node in nodes.iter_mut() {
+        for k in 0..num_children(node) {
+            let ch = resolve(node, k);
+            update(node, k, gen[ch]);
+        }
+    }
+    let map = gen.clone();
+    retrace(nodes, &mut gen);
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tree stored as a structure of arrays, with the children of node `i`
+    /// stored at `children[offsets[i]..offsets[i + 1]]`.
+    struct Columns {
+        vals: Vec<u32>,
+        parents: Vec<Option<usize>>,
+        offsets: Vec<usize>,
+        children: Vec<usize>,
+    }
+
+    impl IndexedTree for Columns {
+        fn len(&self) -> usize {self.vals.len()}
+        fn parent(&self, i: usize) -> Option<usize> {self.parents[i]}
+        fn set_parent(&mut self, i: usize, parent: Option<usize>) {self.parents[i] = parent}
+        fn num_children(&self, i: usize) -> usize {self.offsets[i + 1] - self.offsets[i]}
+        fn child(&self, i: usize, k: usize) -> usize {self.children[self.offsets[i] + k]}
+        fn set_child(&mut self, i: usize, k: usize, child: usize) {
+            self.children[self.offsets[i] + k] = child;
+        }
+        fn swap(&mut self, i: usize, j: usize) {
+            self.vals.swap(i, j);
+            self.parents.swap(i, j);
+            // Move the children of the two nodes, keeping the other nodes in place.
+            let (i, j) = (i.min(j), i.max(j));
+            let (a, b) = (self.offsets[i], self.offsets[i + 1]);
+            let (c, d) = (self.offsets[j], self.offsets[j + 1]);
+            let mut moved: Vec<usize> = self.children[c..d].to_vec();
+            moved.extend_from_slice(&self.children[b..c]);
+            moved.extend_from_slice(&self.children[a..b]);
+            self.children[a..d].copy_from_slice(&moved);
+            let delta = (d - c) as isize - (b - a) as isize;
+            for k in i + 1..=j {
+                self.offsets[k] = (self.offsets[k] as isize + delta) as usize;
+            }
+        }
+    }
+
+    #[test]
+    fn columns() {
+        // The primes example.
+        let mut tree = Columns {
+            vals: vec![2, 6, 2, 12, 3],
+            parents: vec![Some(1), Some(3), Some(3), None, Some(1)],
+            offsets: vec![0, 0, 2, 2, 4, 4],
+            children: vec![4, 0, 2, 1],
+        };
+        sort_indexed(&mut tree);
+        assert_eq!(tree.vals, vec![12, 2, 6, 3, 2]);
+        assert_eq!(tree.parents, vec![None, Some(0), Some(0), Some(2), Some(2)]);
+        assert_eq!(tree.offsets, vec![0, 2, 2, 4, 4, 4]);
+        assert_eq!(tree.children, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn keyed() {
+        use alloc::collections::BTreeMap;
+
+        // Every child is referenced by its key, with a cached position.
+        struct Node {
+            key: char,
+            children: Vec<(char, usize)>,
+        }
+
+        let mut nodes = vec![
+            Node { key: 'c', children: vec![] },
+            Node { key: 'b', children: vec![('c', 0)] },
+            Node { key: 'a', children: vec![('b', 1), ('d', 3)] },
+            Node { key: 'd', children: vec![] },
+        ];
+        let mut positions: BTreeMap<char, usize> =
+            nodes.iter().enumerate().map(|(i, n)| (n.key, i)).collect();
+        let map = sort_keyed(&mut nodes, |n| n.children.len(), |n, k| n.children[k].1,
+            |n, k, new| n.children[k].1 = new);
+        for pos in positions.values_mut() {
+            *pos = map[*pos];
+        }
+        assert_eq!(nodes.iter().map(|n| n.key).collect::<Vec<char>>(), vec!['a', 'b', 'c', 'd']);
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(positions[&node.key], i);
+            for &(key, pos) in &node.children {
+                assert_eq!(positions[&key], pos);
+                assert!(pos > i);
+            }
+        }
+    }
+}
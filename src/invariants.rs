@@ -0,0 +1,82 @@
+//! Checks of the invariants that sorted nodes satisfy, for use in tests and fuzzing.
+//!
+//! Nodes are sorted when both checks succeed, which is what `sort` guarantees.
+//! Every check returns a description of the first violation.
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::Index;
+
+/// Checks that every node is stored after its parent,
+/// and every child is stored after the node listing it.
+pub fn check_child_after_parent<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    -> Result<(), String>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    for (i, node) in nodes.iter_mut().enumerate() {
+        if let Some(p) = *parent(node) {
+            let p = p.to_usize();
+            if p >= i {
+                return Err(format!("node `{}` is stored before its parent `{}`", i, p));
+            }
+        }
+        for ch in children(node).iter() {
+            let ch = ch.to_usize();
+            if ch <= i {
+                return Err(format!("child `{}` is stored before its parent `{}`", ch, i));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every sibling is stored after previous siblings.
+pub fn check_sibling_order<T, I, C>(nodes: &mut [T], children: C) -> Result<(), String>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    for node in nodes.iter_mut() {
+        for w in children(node).windows(2) {
+            let (ch, next) = (w[0].to_usize(), w[1].to_usize());
+            if ch >= next {
+                return Err(format!("sibling `{}` is stored before its previous sibling `{}`",
+                    next, ch));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    struct Node {
+        parent: Option<usize>,
+        children: Vec<usize>,
+    }
+
+    #[test]
+    fn violations() {
+        let mut nodes = vec![
+            Node { parent: None, children: vec![2, 1] },
+            Node { parent: Some(0), children: vec![] },
+            Node { parent: Some(0), children: vec![] },
+        ];
+        assert_eq!(check_child_after_parent(&mut nodes, |n| &mut n.parent, |n| &mut n.children),
+            Ok(()));
+        assert_eq!(check_sibling_order(&mut nodes, |n| &mut n.children),
+            Err("sibling `1` is stored before its previous sibling `2`".into()));
+
+        nodes[0].children = vec![1, 2];
+        nodes[1].parent = Some(2);
+        assert_eq!(check_child_after_parent(&mut nodes, |n| &mut n.parent, |n| &mut n.children),
+            Err("node `1` is stored before its parent `2`".into()));
+        assert_eq!(check_sibling_order(&mut nodes, |n| &mut n.children), Ok(()));
+    }
+}
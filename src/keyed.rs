@@ -0,0 +1,327 @@
+//! Sorting where nodes without ordering constraints between them are ordered by a key
+//! or a priority.
+
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::{constraints, retrace, rewrite_indices, Index};
+
+/// Sorts a tree such that every child is greater than their parent,
+/// every sibling is greater than previous siblings,
+/// and otherwise nodes with smaller keys come first.
+///
+/// Unlike `sort`, which keeps nodes close to their original location,
+/// the result depends only on the shape of the tree and the keys,
+/// except for nodes with equal keys, which keep their relative order.
+/// This makes it possible to get the same layout for trees built in different ways.
+///
+/// The nodes are placed one at a time, choosing the node with the smallest key
+/// among the nodes whose parent and previous siblings are already placed.
+///
+/// Panics if the parents and children contain a cycle.
+pub fn sort_by_key<T, I, P, C, K, F>(nodes: &mut [T], parent: P, children: C, key: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          K: Ord,
+          F: Fn(&T) -> K
+{
+    let keys: Vec<K> = nodes.iter().map(key).collect();
+    sort_by_keys(nodes, &parent, &children, &keys, |i, _| i);
+}
+
+/// Sorts a tree such that every child is greater than their parent,
+/// every sibling is greater than previous siblings,
+/// and otherwise nodes with lower `priority` come first.
+///
+/// Unlike `sort_by_key`, the order of siblings is also decided by the priority,
+/// with siblings of equal priority keeping their order, so the children lists are reordered.
+/// When the priority conflicts with the tree, the tree wins:
+/// a node with a lower priority than its parent is still stored after its parent,
+/// but before other nodes that are available when it is.
+///
+/// Panics if `priority` does not have the same length as `nodes`,
+/// or if the parents and children contain a cycle.
+pub fn sort_with_priority<T, I, P, C>(nodes: &mut [T], parent: P, children: C, priority: &[u64])
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    assert_eq!(priority.len(), nodes.len(), "expected one priority per node");
+    for node in nodes.iter_mut() {
+        children(node).sort_by_key(|ch| priority[ch.to_usize()]);
+    }
+    sort_by_keys(nodes, &parent, &children, priority, |i, _| i);
+}
+
+/// Sorts a tree such that every child is greater than their parent,
+/// every sibling is greater than previous siblings,
+/// and otherwise nodes with smaller keys come first,
+/// where the result does not depend on the original order of the nodes.
+///
+/// This is like `sort_by_key`, but nodes with equal keys are ordered by the tree instead
+/// of their original order:
+/// a node whose parent is stored earlier comes first,
+/// and roots with equal keys are ordered by comparing the keys and number of children
+/// of their subtrees in depth first order.
+/// When the key identifies the value of a node,
+/// two layouts of the same tree are sorted into the same nodes,
+/// which makes the result usable as a canonical form.
+/// Roots are stored before other nodes with equal keys.
+///
+/// Panics if the parents and children contain a cycle.
+pub fn sort_canonical<T, I, P, C, K, F>(nodes: &mut [T], parent: P, children: C, key: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          K: Ord,
+          F: Fn(&T) -> K
+{
+    let n = nodes.len();
+    let keys: Vec<K> = nodes.iter().map(key).collect();
+    let parents: Vec<Option<usize>> =
+        nodes.iter_mut().map(|node| parent(node).map(|p| p.to_usize())).collect();
+
+    // Describe every tree by the keys and number of children of its nodes in depth first order.
+    let mut trees: Vec<(Vec<(&K, usize)>, usize)> = vec![];
+    let mut stack: Vec<usize> = vec![];
+    for root in (0..n).filter(|&i| parents[i].is_none()) {
+        let mut desc = vec![];
+        stack.push(root);
+        while let Some(i) = stack.pop() {
+            assert!(desc.len() < n, "parents and children contain a cycle");
+            let children = children(&mut nodes[i]);
+            desc.push((&keys[i], children.len()));
+            stack.extend(children.iter().rev().map(|ch| ch.to_usize()));
+        }
+        trees.push((desc, root));
+    }
+    trees.sort();
+    let mut rank = vec![0; n];
+    for (r, &(_, root)) in trees.iter().enumerate() {
+        rank[root] = r;
+    }
+
+    sort_by_keys(nodes, &parent, &children, &keys, |i, gen| match parents[i] {
+        None => rank[i],
+        Some(p) => n + gen[p],
+    });
+}
+
+/// Sorts a tree such that every child is greater than their parent,
+/// every sibling is greater than previous siblings,
+/// and otherwise nodes keep their relative order from before sorting.
+///
+/// The nodes are placed one at a time, choosing the node stored first
+/// among the nodes whose parent and previous siblings are already placed.
+/// This guarantees that a node `a` stored before a node `b` stays before `b`,
+/// when every node that must be stored before `a` is also stored before `b`.
+/// Unlike `sort`, which keeps nodes close to their original location
+/// without guaranteeing their relative order, this is a stable topological sort,
+/// which is useful for keeping diffs small.
+///
+/// Panics if the parents and children contain a cycle.
+pub fn sort_stable<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let keys: Vec<usize> = (0..nodes.len()).collect();
+    sort_by_keys(nodes, &parent, &children, &keys, |i, _| i);
+}
+
+/// Places the nodes one at a time, choosing the node with the smallest key
+/// among the nodes whose parent and previous siblings are already placed.
+///
+/// Nodes with equal keys are ordered by `tie`, which is called when a node becomes available
+/// with the node and the new positions of the nodes that are already placed.
+fn sort_by_keys<T, I, P, C, K, F>(nodes: &mut [T], parent: &P, children: &C, keys: &[K], tie: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          K: Ord,
+          F: Fn(usize, &[usize]) -> usize
+{
+    let n = nodes.len();
+    let (offsets, targets) = constraints(nodes, children);
+    let mut indegree = vec![0; n];
+    for &b in &targets {
+        indegree[b] += 1;
+    }
+
+    let mut gen = vec![0; n];
+    let mut ready: BinaryHeap<Reverse<(&K, usize, usize)>> = (0..n)
+        .filter(|&i| indegree[i] == 0)
+        .map(|i| Reverse((&keys[i], tie(i, &gen), i)))
+        .collect();
+    for t in 0..n {
+        let Reverse((_, _, a)) = ready.pop().expect("parents and children contain a cycle");
+        gen[a] = t;
+        for &b in &targets[offsets[a]..offsets[a + 1]] {
+            indegree[b] -= 1;
+            if indegree[b] == 0 {
+                ready.push(Reverse((&keys[b], tie(b, &gen), b)));
+            }
+        }
+    }
+    rewrite_indices(nodes, parent, children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, random_forest};
+
+    #[derive(Clone, PartialEq, Debug)]
+    struct Node {
+        val: char,
+        parent: Option<usize>,
+        children: Vec<usize>,
+    }
+
+    fn vals(nodes: &[Node]) -> Vec<char> {
+        nodes.iter().map(|n| n.val).collect()
+    }
+
+    #[test]
+    fn by_key() {
+        // The cousins `x` and `y` are ordered by key, while siblings keep their order.
+        let mut nodes = vec![
+            Node { val: 'y', parent: Some(2), children: vec![] },
+            Node { val: 'x', parent: Some(3), children: vec![] },
+            Node { val: 'c', parent: Some(4), children: vec![0] },
+            Node { val: 'b', parent: Some(4), children: vec![1] },
+            Node { val: 'a', parent: None, children: vec![2, 3] },
+        ];
+        sort_by_key(&mut nodes, |n| &mut n.parent, |n| &mut n.children, |n| n.val);
+        assert_eq!(vals(&nodes), vec!['a', 'c', 'b', 'x', 'y']);
+        assert_eq!(nodes[0].children, vec![1, 2]);
+        assert_eq!(nodes[1].children, vec![4]);
+        assert_eq!(nodes[2].children, vec![3]);
+
+        // A different layout of the same tree gives the same result.
+        let mut other = vec![
+            Node { val: 'a', parent: None, children: vec![3, 1] },
+            Node { val: 'b', parent: Some(0), children: vec![2] },
+            Node { val: 'x', parent: Some(1), children: vec![] },
+            Node { val: 'c', parent: Some(0), children: vec![4] },
+            Node { val: 'y', parent: Some(3), children: vec![] },
+        ];
+        sort_by_key(&mut other, |n| &mut n.parent, |n| &mut n.children, |n| n.val);
+        assert_eq!(other, nodes);
+    }
+
+    #[test]
+    fn priority() {
+        // Siblings are ordered by priority, and `x` is pulled before `c` by its low priority.
+        let mut nodes = vec![
+            Node { val: 'a', parent: None, children: vec![1, 2] },
+            Node { val: 'c', parent: Some(0), children: vec![] },
+            Node { val: 'b', parent: Some(0), children: vec![3] },
+            Node { val: 'x', parent: Some(2), children: vec![] },
+        ];
+        sort_with_priority(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &[0, 3, 1, 0]);
+        assert_eq!(vals(&nodes), vec!['a', 'b', 'x', 'c']);
+        assert_eq!(nodes[0].children, vec![1, 3]);
+        assert_eq!(nodes[1].children, vec![2]);
+    }
+
+    #[test]
+    fn canonical() {
+        // The same forest in two layouts, with the roots `r` and the leaves `x` having equal keys.
+        // r
+        // |- a
+        //    |- x
+        //    |- b
+        // |- c
+        //    |- x
+        // r
+        // |- a
+        let mut nodes = vec![
+            Node { val: 'x', parent: Some(5), children: vec![] },
+            Node { val: 'r', parent: None, children: vec![2] },
+            Node { val: 'a', parent: Some(1), children: vec![] },
+            Node { val: 'b', parent: Some(6), children: vec![] },
+            Node { val: 'x', parent: Some(6), children: vec![] },
+            Node { val: 'c', parent: Some(7), children: vec![0] },
+            Node { val: 'a', parent: Some(7), children: vec![4, 3] },
+            Node { val: 'r', parent: None, children: vec![6, 5] },
+        ];
+        let mut other = vec![
+            Node { val: 'r', parent: None, children: vec![3, 2] },
+            Node { val: 'x', parent: Some(3), children: vec![] },
+            Node { val: 'c', parent: Some(0), children: vec![4] },
+            Node { val: 'a', parent: Some(0), children: vec![1, 7] },
+            Node { val: 'x', parent: Some(2), children: vec![] },
+            Node { val: 'r', parent: None, children: vec![6] },
+            Node { val: 'a', parent: Some(5), children: vec![] },
+            Node { val: 'b', parent: Some(3), children: vec![] },
+        ];
+        sort_canonical(&mut nodes, |n| &mut n.parent, |n| &mut n.children, |n| n.val);
+        sort_canonical(&mut other, |n| &mut n.parent, |n| &mut n.children, |n| n.val);
+        assert_eq!(nodes, other);
+        assert_eq!(vals(&nodes), vec!['r', 'a', 'r', 'a', 'c', 'x', 'b', 'x']);
+        crate::assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+    }
+
+    #[test]
+    fn stable() {
+        // The root `c` is stored before the root `d`, which `sort` moves to the front.
+        let mut nodes = vec![
+            Node { val: 'a', parent: Some(3), children: vec![1] },
+            Node { val: 'b', parent: Some(0), children: vec![] },
+            Node { val: 'c', parent: None, children: vec![] },
+            Node { val: 'd', parent: None, children: vec![0] },
+        ];
+        let mut unstable = nodes.clone();
+        crate::sort(&mut unstable, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(vals(&unstable), vec!['d', 'a', 'c', 'b']);
+        sort_stable(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(vals(&nodes), vec!['c', 'd', 'a', 'b']);
+        assert_eq!(nodes[1].children, vec![2]);
+        assert_eq!(nodes[2].parent, Some(1));
+
+        let mut seed: u64 = 73;
+        for n in 0..30 {
+            let mut nodes = random_forest(n, &mut seed);
+            for (i, node) in nodes.iter_mut().enumerate() {
+                node.val = i as u32;
+            }
+            // The last node stored before sorting that must be stored before every node,
+            // found by searching the constraints from every node.
+            let (offsets, targets) =
+                crate::constraints(&mut nodes, &|n: &mut test_util::Node| &mut n.children[..]);
+            let mut latest: Vec<Option<usize>> = vec![None; n];
+            for w in 0..n {
+                let mut visited = vec![false; n];
+                let mut stack = vec![w];
+                while let Some(a) = stack.pop() {
+                    for &b in &targets[offsets[a]..offsets[a + 1]] {
+                        if !visited[b] {
+                            visited[b] = true;
+                            latest[b] = Some(w);
+                            stack.push(b);
+                        }
+                    }
+                }
+            }
+
+            sort_stable(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            crate::assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            let mut new = vec![0; n];
+            for (i, node) in nodes.iter().enumerate() {
+                new[node.val as usize] = i;
+            }
+            for a in 0..n {
+                for b in a + 1..n {
+                    if latest[a].iter().all(|&w| w < b) {
+                        assert!(new[a] < new[b]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -23,6 +23,9 @@
 //! `c, a, b` => `(b), a, (c)` => `(a, b), c` => `a, b, c` (retrace phase)
 //! ```
 //!
+//! The phases are available as separate functions for inspecting intermediate state:
+//! `solve_generator`, `rewrite_indices` and `retrace`.
+//!
 //!
 //! ### Primes example
 //!
@@ -105,16 +108,16 @@
 //!             children: vec![],
 //!         }, // 4
 //!     ];
-//!     for i in 0..nodes.len() {
-//!         println!("{}: {:?}", i, nodes[i]);
+//!     for (i, node) in nodes.iter().enumerate() {
+//!         println!("{}: {:?}", i, node);
 //!     }
 //!     // Prints `[2, 6, 2, 12, 3]`
 //!     println!("{:?}", nodes.iter().map(|n| n.value).collect::<Vec<u32>>());
 //!
 //!     sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-//!     println!("");
-//!     for i in 0..nodes.len() {
-//!         println!("{}: {:?}", i, nodes[i]);
+//!     println!();
+//!     for (i, node) in nodes.iter().enumerate() {
+//!         println!("{}: {:?}", i, node);
 //!     }
 //!     // Prints `[12, 2, 6, 3, 2]`
 //!     println!("{:?}", nodes.iter().map(|n| n.value).collect::<Vec<u32>>());
@@ -125,11 +128,37 @@
 //!
 //! The `sort` algorithm assumes that each node is referenced by maximum one parent.
 //! If you share nodes between parent nodes, the algorithm might enter an infinite loop.
+//! In debug builds, `sort` detects shared nodes and panics instead.
 //!
 //! One can use `sort_dag` to sort a tree where nodes can have multiple parents.
 //! In order for the algorithm to work with shared nodes,
 //! the tree must be a Directed Acyclic Graph (DAG).
 //! If the tree is not a DAG, the algorithm will run in an infinite loop.
+//! Use `try_sort_dag` to get an error instead.
+//!
+//! ### No-std support
+//!
+//! The only requirement of this library is a global allocator.
+//! To use it with `#![no_std]`, disable the default `std` feature:
+//!
+//! ```toml
+//! [dependencies]
+//! advancedresearch-tree_mem_sort = { version = "0.2", default-features = false }
+//! ```
+//!
+//! ### Parallel sorting
+//!
+//! For large trees, enable the `rayon` feature to use `par_sort`.
+//! It searches for nodes that are out of order in parallel,
+//! while performing swaps in the same order as `sort`, so the result is identical.
+//!
+//! ### Determinism
+//!
+//! The result of sorting depends only on the nodes, parents and children,
+//! never on the platform, pointer addresses or hashing with random state.
+//! The same input gives the same output on every platform, which makes it suitable
+//! for reproducible builds and content-addressing.
+//! Functions taking callbacks are deterministic when the callbacks are.
 //!
 //! ### Why topological sort on trees? Why not use DAG representation?
 //!
@@ -159,7 +188,10 @@
 //! However, since `D` is a child of `C` it must be greater than `C`.
 //! This leads to a contradiction.
 //!
-//! If you try to sort the tree above using `sort_dag`, it will run in an infinite loop.
+//! If you try to sort the tree above using `sort_dag`, it will run in an infinite loop,
+//! while `try_sort_dag` returns a `CycleError`.
+//! Use `dag_is_consistent` to check this before sorting.
+//! To build children that never contradict the edges of a DAG, use `from_adjacency`.
 //!
 //! Trees are easy to reason about and has a more efficient encoding for this library's common usage.
 //! For `N` children, the arrows of an equivalent DAG requires at least `N` arrows.
@@ -181,844 +213,5443 @@
 //! at the end of the array storing the nodes, which node's parent is any existing node,
 //! then the new tree is topologically sorted.
 //! The same is not true for indices built from tree traversal.
+//! To append a subtree of several nodes, use `append_subtree`.
 
 #![deny(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::hash::Hasher;
+use core::ops::Range;
+
+pub use indexed::{sort_indexed, sort_keyed, IndexedTree};
+pub use keyed::{sort_by_key, sort_canonical, sort_stable, sort_with_priority};
+pub use newick::{from_newick, to_newick, NewickLabel, ParseError};
+pub use node::{node, Nested, Node, TreeBuilder};
+#[cfg(feature = "rayon")]
+pub use par::par_sort;
+pub use pinned::{sort_pinned, Unsatisfiable};
+pub use rc_tree::{flatten_rc, sort_rc};
+pub use step::{sort_step, Progress, SortState};
+pub use storage::{sort_storage, Storage};
+pub use tree::Tree;
+
+mod indexed;
+pub mod invariants;
+mod keyed;
+mod newick;
+mod node;
+#[cfg(feature = "rayon")]
+mod par;
+mod pinned;
+mod rc_tree;
+mod siblings;
+mod step;
+mod storage;
+#[cfg(test)]
+mod test_util;
+mod tree;
+mod worklist;
+
+/// An integer type used to store node indices.
+///
+/// This makes it possible to use e.g. `u32` instead of `usize` to save memory.
+/// The group generator always uses `usize` internally.
+pub trait Index: Copy {
+    /// Converts from `usize`.
+    fn from_usize(i: usize) -> Self;
+    /// Converts to `usize`.
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_index {
+    ($($t:ty),*) => {$(
+        impl Index for $t {
+            #[inline(always)]
+            fn from_usize(i: usize) -> Self {
+                debug_assert!(i <= <$t>::MAX as usize, "index does not fit in `{}`", stringify!($t));
+                i as $t
+            }
+            #[inline(always)]
+            fn to_usize(self) -> usize {self as usize}
+        }
+    )*}
+}
+
+impl_index!(u8, u16, u32, u64, usize);
 
 /// Performs in-memory topological sort on a tree where
 /// order is determined by every child being greater than their parent,
 /// and every sibling being greater than previous siblings.
-pub fn sort<T, P, C>(nodes: &mut [T], parent: P, children: C)
-    where P: Fn(&mut T) -> &mut Option<usize>,
-          C: Fn(&mut T) -> &mut [usize]
+///
+/// In debug builds, this panics if a node is listed as a child by more than one parent,
+/// which would otherwise result in an infinite loop.
+///
+/// Subtrees are not guaranteed to be stored contiguously,
+/// use `compact_subtrees` after sorting when this is needed.
+///
+/// Use `sort_storage` for nodes in other collections than slices, such as `VecDeque`.
+///
+/// Trees with at most 32 nodes are sorted without allocating memory in release builds.
+pub fn sort<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    if n <= SMALL {
+        // Avoid allocating the generator for small trees.
+        let mut buf = [0; SMALL];
+        let gen = &mut buf[..n];
+        for (i, g) in gen.iter_mut().enumerate() {
+            *g = i;
+        }
+        sort_unbounded(nodes, &parent, &children, gen);
+    } else {
+        sort_with_scratch(nodes, parent, children, &mut vec![]);
+    }
+}
+
+/// The largest number of nodes that `sort` sorts without allocating memory.
+const SMALL: usize = 32;
+
+/// The same algorithm as `sort`, but for parents stored as an index
+/// where the value `none` means that the node has no parent.
+///
+/// This is useful for nodes that are shared with other languages,
+/// for example using `usize::MAX` for roots.
+pub fn sort_sentinel<T, I, P, C>(nodes: &mut [T], parent: P, children: C, none: I)
+    where I: Index,
+          P: Fn(&mut T) -> &mut I,
+          C: Fn(&mut T) -> &mut [I]
 {
-    // This problem can be solved efficiently using Group Theory.
-    // This avoids the need for cloning nodes into a new array,
-    // while performing the minimum work to get a normalized tree.
-    //
-    // Create a group generator that is modified by swapping to find a solution.
-    // The group generator keeps track of indices, such that child-parent relations
-    // do not have to change until later.
-    //
-    // Use the order in the generator to detect whether a swap has been performed.
-    // The condition for swapping `a, b` is `gen[a] > gen[b]`.
     let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    let none = none.to_usize();
+    for node in nodes.iter_mut() {
+        let p = parent(node);
+        if p.to_usize() != none {
+            *p = I::from_usize(gen[p.to_usize()]);
+        }
+        for ch in children(node) {
+            *ch = I::from_usize(gen[ch.to_usize()])
+        }
+    }
+    retrace(nodes, &mut gen);
+}
+
+/// The same algorithm as `sort`, but for one-based indices
+/// where a parent of `0` means that the node has no parent.
+///
+/// The node stored at `nodes[0]` has index `1`, and so on.
+/// The sort works directly on one-based indices, without translating them.
+/// This is useful for nodes that are shared with languages using `0` as null.
+pub fn sort_one_based<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut I,
+          C: Fn(&mut T) -> &mut [I]
+{
+    // The generator has an extra slot for the null index, which is never swapped.
+    let mut gen: Vec<usize> = (0..nodes.len() + 1).collect();
+    let mut scratch = vec![];
+    let mut passes = 0;
     loop {
+        passes += 1;
         let mut changed = false;
-        for i in 0..nodes.len() {
-            let children = children(&mut nodes[i]);
-            for j in 0..children.len() {
-                let a = children[j];
-                // Store child after its parent.
-                if gen[i] > gen[a] {
-                    gen.swap(i, a);
-                    changed = true;
-                }
-                // Check all pairs of children.
-                for k in j + 1..children.len() {
-                    let b = children[k];
-
-                    // Store children in sorted order.
-                    if gen[a] > gen[b] {
-                        gen.swap(a, b);
-                        changed = true;
-                    }
-                }
-            }
+        for (i, node) in nodes.iter_mut().enumerate() {
+            changed |= visit(i + 1, children(node), &mut gen, SortOrder::Ascending,
+                &mut scratch) > 0;
         }
         if !changed {
             break;
         }
     }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    for node in nodes.iter_mut() {
+        let p = parent(node);
+        *p = I::from_usize(gen[p.to_usize()]);
+        for ch in children(node) {
+            *ch = I::from_usize(gen[ch.to_usize()])
+        }
+    }
+    let mut gen: Vec<usize> = gen[1..].iter().map(|&g| g - 1).collect();
+    retrace(nodes, &mut gen);
+}
 
-    // Update the tree data with the new indices from the generator.
-    // Do this before performing the actual swapping,
-    // since the generator maps from old indices to new indices.
-    for i in 0..nodes.len() {
-        let p = parent(&mut nodes[i]);
-        *p = p.map(|p| gen[p]);
-        for ch in children(&mut nodes[i]) {
-            *ch = gen[*ch]
+/// The same algorithm as `sort`, but for children stored in slots that might be empty.
+///
+/// Empty slots are skipped, such that every present child is greater than the present
+/// children in previous slots.
+/// This is useful for nodes with a fixed number of optional children,
+/// for example a binary operator with an optional operand.
+pub fn sort_optional<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [Option<I>]
+{
+    let n = nodes.len();
+    let mut gen: Vec<usize> = (0..n).collect();
+    // Collect the present children of each node once per pass.
+    let mut buf: Vec<usize> = vec![];
+    let mut scratch = vec![];
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for (i, node) in nodes.iter_mut().enumerate() {
+            buf.clear();
+            buf.extend(children(node).iter().filter_map(|ch| ch.map(|ch| ch.to_usize())));
+            changed |= visit(i, &buf, &mut gen, SortOrder::Ascending, &mut scratch) > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    for node in nodes.iter_mut() {
+        let p = parent(node);
+        *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+        for ch in children(node) {
+            *ch = ch.map(|ch| I::from_usize(gen[ch.to_usize()]));
         }
     }
+    retrace(nodes, &mut gen);
+}
 
-    // Swap nodes using the group generator as guide.
-    // When swapping has been performed, update the generator to keep track of state.
-    // This is because multiple swaps sharing elements might require multiple steps.
-    //
-    // The order which swaps are retraced might be different than the solving phase:
-    //
-    // `a, b, c` => `a, (c, b)` => `(c, a), b` => `c, a, b` (solving phase)
-    // `c, a, b` => `(b), a, (c)` => `(a, b), c` => `a, b, c` (retrace phase)
-    //
-    // However, since the generator solution is produced by swapping operations,
-    // it is guaranteed to be restorable to the identity generator when retracing.
-    // In fact, it works for any permutation of the identity generator.
-    //
-    // There is no need to loop more than once because each index is stored uniquely by lookup,
-    // such that if `g[i] = i` then there exists no `j != i` such that `g[j] = i`.
-    //
-    // Retrace by putting nodes where they belong (`g[i]`).
-    // Once a node is put where it belongs, no need further work is needed for `g[i]`.
-    // It means that the problem has been reduced from size `n` to `n-1`.
-    //
-    // However, in order to put one node from `i` to where it belongs `g[i]`,
-    // one must also keep track of the node who occupied the previous `j = g[i]`.
-    // Not only keep track of the node data, but where it belongs `g[j]` too.
-    //
-    // Fortunately, the old position (`i`) is free as a temporary location.
-    // This is why `i` and `g[i]` are swapped.
-    // The same swap is done in the group generator to track where the new node belongs:
-    // `gen[j] => gen[i]`
-    //
-    // This procedure is repeated for the new node `i`,
-    // putting it where it belongs `g[i]`, and in return receive a new task `gen[j] => gen[i]`.
-    // The task is repeated until `i` belongs to where it should be,
-    // then it goes to the next step, where the same procedure is repeated.
-    // All nodes which have previously been put where they belong does not need any work,
-    // and there is no need to go back, since no node will be swapped with an earlier location.
-    for i in 0..nodes.len() {
-        while gen[i] != i {
-            let j = gen[i];
-            nodes.swap(i, j);
-            gen.swap(i, j);
+/// The order of siblings used by `sort_ordered`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Every sibling is greater than previous siblings.
+    Ascending,
+    /// Every sibling is less than previous siblings.
+    Descending,
+}
+
+/// The same algorithm as `sort`, but with a choice of sibling order.
+///
+/// Every child is greater than their parent in both orders.
+/// `SortOrder::Ascending` gives the same result as `sort`.
+pub fn sort_ordered<T, I, P, C>(nodes: &mut [T], parent: P, children: C, order: SortOrder)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) = solve(nodes, &children, &mut gen, order, usize::MAX, usize::MAX)
+        .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// Implemented by nodes of a tree, to sort without passing accessors.
+///
+/// ```rust
+/// use tree_mem_sort::{sort_nodes, TreeNode};
+///
+/// pub struct Number {
+///     pub value: u32,
+///     pub parent: Option<usize>,
+///     pub children: Vec<usize>,
+/// }
+///
+/// impl TreeNode for Number {
+///     type Index = usize;
+///     fn parent(&mut self) -> &mut Option<usize> {&mut self.parent}
+///     fn children(&mut self) -> &mut [usize] {&mut self.children}
+/// }
+///
+/// let mut nodes = vec![
+///     Number {value: 2, parent: Some(1), children: vec![]},
+///     Number {value: 6, parent: None, children: vec![0]},
+/// ];
+/// sort_nodes(&mut nodes);
+/// assert_eq!(nodes.iter().map(|n| n.value).collect::<Vec<u32>>(), vec![6, 2]);
+/// ```
+pub trait TreeNode {
+    /// The type of node indices.
+    type Index: Index;
+    /// Returns the parent of the node.
+    fn parent(&mut self) -> &mut Option<Self::Index>;
+    /// Returns the children of the node.
+    fn children(&mut self) -> &mut [Self::Index];
+}
+
+/// Implemented by nodes of a Directed Acyclic Graph (DAG), to sort without passing accessors.
+pub trait DagNode {
+    /// The type of node indices.
+    type Index: Index;
+    /// Returns the parents of the node.
+    fn parents(&mut self) -> &mut [Self::Index];
+    /// Returns the children of the node.
+    fn children(&mut self) -> &mut [Self::Index];
+}
+
+/// Sorts nodes that implement `TreeNode`, see `sort`.
+pub fn sort_nodes<T: TreeNode>(nodes: &mut [T]) {
+    sort(nodes, T::parent, T::children)
+}
+
+/// Sorts boxed trait objects of nodes, such as nodes of different types, see `sort`.
+///
+/// `TreeNode` is object safe when the index type is specified,
+/// so nodes from different crates can be sorted together through dynamic dispatch.
+pub fn sort_dyn<I: Index>(nodes: &mut [Box<dyn TreeNode<Index = I>>]) {
+    sort(nodes, |n| n.parent(), |n| n.children())
+}
+
+/// Sorts nodes that implement `DagNode`, see `sort_dag`.
+pub fn sort_dag_nodes<T: DagNode>(nodes: &mut [T]) {
+    sort_dag(nodes, T::parents, T::children)
+}
+
+/// The same algorithm as `sort`, but reuses a buffer for the group generator.
+///
+/// This avoids allocating memory when sorting many small trees.
+/// The buffer is cleared before use, so it can have any length or contents.
+pub fn sort_with_scratch<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+    scratch: &mut Vec<usize>,
+)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    scratch.clear();
+    scratch.extend(0..nodes.len());
+    sort_unbounded(nodes, &parent, &children, scratch);
+}
+
+/// The same algorithm as `sort`, but starts the solving phase from the generator `gen`
+/// instead of the identity generator.
+///
+/// Every node `i` is initially assumed to be stored at `gen[i]`,
+/// such as the generator from `sort_permutation` of a similar tree.
+/// The solving phase continues until every child is greater than their parent,
+/// and every sibling is greater than previous siblings,
+/// so the result is sorted for any permutation of `0..nodes.len()`.
+/// However, the result might differ from `sort` when `gen` is not the identity,
+/// since the nodes are kept close to where `gen` puts them.
+///
+/// If `gen` does not have the same length as `nodes`, it is reset to the identity generator,
+/// which covers nodes being added or removed since the previous sort.
+/// Afterwards, `gen` is left as the identity generator.
+///
+/// Panics if `gen` has the same length as `nodes`, but is not a permutation.
+pub fn sort_resume<T, I, P, C>(nodes: &mut [T], parent: P, children: C, gen: &mut Vec<usize>)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    if gen.len() != n {
+        gen.clear();
+        gen.extend(0..n);
+    }
+    assert!(is_permutation(gen, n), "expected a permutation of `0..{}`", n);
+    let (passes, _) = solve(nodes, &children, gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+        .expect("solving phase has no limit");
+    if passes == 1 && gen.iter().enumerate().all(|(i, &g)| g == i) {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, gen);
+    retrace(nodes, gen);
+}
+
+/// The phase of the algorithm that performed a swap, see `sort_observed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// A swap of the group generator in the solving phase.
+    Solve,
+    /// A swap of node data in the retrace phase.
+    Retrace,
+}
+
+/// The same algorithm as `sort`, but calls `on_swap` with the phase and indices of every swap.
+///
+/// In the solving phase, the indices are the nodes whose new positions are swapped
+/// in the group generator, while the node data stays in place.
+/// In the retrace phase, the indices are the positions of node data that is swapped.
+/// This is useful for visualizing how the algorithm works, such as animating the sort.
+///
+/// The result is the same as `sort`.
+/// Every pair of children is compared, so this is slower than `sort` for nodes with many children.
+pub fn sort_observed<T, I, P, C, F>(nodes: &mut [T], parent: P, children: C, mut on_swap: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          F: FnMut(Phase, usize, usize)
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let swaps = visit_pairs_with(i, children(node), &mut gen, SortOrder::Ascending,
+                |a, b| on_swap(Phase::Solve, a, b));
+            changed |= swaps > 0;
         }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
     }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace_with(nodes, &mut gen, |i, j| on_swap(Phase::Retrace, i, j));
 }
 
-/// The same algorithm as `sort`, but for Directed Acyclic Graphs (DAGs),
-/// encoded as trees with shared nodes.
+/// The same algorithm as `sort`, but returns the number of passes of the solving phase,
+/// including the final pass that detected no changes.
 ///
-/// WARNING: To avoid an infinite loop, one must be careful about the order of children.
-/// E.g. if `A` has children `C, B` and `B` has child `C`, then the tree is not a DAG.
-/// This is because the order of children is preserved after sorting.
-pub fn sort_dag<T, P, C>(nodes: &mut [T], parents: P, children: C)
-    where P: Fn(&mut T) -> &mut [usize],
-          C: Fn(&mut T) -> &mut [usize]
+/// A return value of `1` means that the nodes were already sorted.
+/// Larger values mean that the nodes were further from sorted,
+/// which is a cheap measure of disorder.
+pub fn sort_with_passes<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> usize
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    sort_unbounded(nodes, &parent, &children, &mut gen)
+}
+
+/// Sorts a tree starting from the identity generator `gen`, without limiting the passes.
+///
+/// Returns the number of passes.
+/// In debug builds, this panics if a node is listed as a child by more than one parent.
+fn sort_unbounded<S, I, P, C>(nodes: &mut S, parent: &P, children: &C, gen: &mut [usize])
+    -> usize
+    where S: Storage + ?Sized,
+          I: Index,
+          P: Fn(&mut S::Output) -> &mut Option<I>,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    debug_check_shared(nodes, children);
+    sort_with_gen(nodes, parent, children, gen, usize::MAX)
+        .expect("solving phase has no limit")
+}
+
+/// The same algorithm as `sort`, but visits the children through a callback
+/// instead of requiring them to be stored in a slice.
+///
+/// The `children` closure must call the visitor with every child index of the node, in order.
+/// This makes it possible to store children in any collection
+/// that hands out mutable references, such as `VecDeque` or a small vector.
+///
+/// For example: `|n, f| n.children.iter_mut().for_each(f)`.
+///
+/// The children are collected once into a buffer, which is solved like `sort`.
+/// In debug builds, this panics if a node is listed as a child by more than one parent.
+pub fn sort_each<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T, &mut dyn FnMut(&mut I))
+{
+    let n = nodes.len();
+    let mut pool: Vec<usize> = vec![];
+    let mut ends: Vec<usize> = Vec::with_capacity(n);
+    for node in nodes.iter_mut() {
+        children(node, &mut |ch| pool.push(ch.to_usize()));
+        ends.push(pool.len());
+    }
+    // Split the buffer into the children of every node.
+    let mut lists: Vec<&mut [usize]> = Vec::with_capacity(n);
+    let mut rest = &mut pool[..];
+    let mut start = 0;
+    for &end in &ends {
+        let (list, tail) = core::mem::take(&mut rest).split_at_mut(end - start);
+        lists.push(list);
+        rest = tail;
+        start = end;
+    }
+    fn list<'a>(list: &'a mut &mut [usize]) -> &'a mut [usize] {list}
+    debug_check_shared(&mut lists[..], &list);
+
+    let mut gen: Vec<usize> = (0..n).collect();
+    let (passes, _) = solve(&mut lists[..], &list, &mut gen, SortOrder::Ascending,
+        usize::MAX, usize::MAX)
+        .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+
+    for node in nodes.iter_mut() {
+        let p = parent(node);
+        *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+        children(node, &mut |ch| *ch = I::from_usize(gen[ch.to_usize()]));
+    }
+
+    retrace(nodes, &mut gen);
+}
+
+/// Sorts a tree such that every child is greater than their parent,
+/// without ordering siblings.
+///
+/// This is a weaker ordering than `sort`, which is cheaper to compute
+/// when the order of siblings is irrelevant.
+/// The result is still a valid topological order of the parent-child relations,
+/// but it is not canonical, since siblings might be stored in any order.
+pub fn sort_parents_only<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
 {
     let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let mut passes = 0;
     loop {
+        passes += 1;
         let mut changed = false;
-        for i in 0..nodes.len() {
-            let children = children(&mut nodes[i]);
-            for j in 0..children.len() {
-                let a = children[j];
-                // Store child after its parent.
-                if gen[i] > gen[a] {
-                    gen.swap(i, a);
+        for (i, node) in nodes.iter_mut().enumerate() {
+            for ch in children(node).iter() {
+                let j = ch.to_usize();
+                if gen[i] > gen[j] {
+                    gen.swap(i, j);
                     changed = true;
                 }
-                // Check all pairs of children.
-                for k in j + 1..children.len() {
-                    let b = children[k];
-
-                    // Store children in sorted order.
-                    if gen[a] > gen[b] {
-                        gen.swap(a, b);
-                        changed = true;
-                    }
-                }
             }
         }
         if !changed {
             break;
         }
     }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
 
+/// The same algorithm as `sort`, but first orders the children of every node by `cmp`.
+///
+/// The comparator fully determines the order of siblings,
+/// while every child is still stored after its parent.
+/// Siblings that compare equal keep their relative order.
+/// The children lists are reordered before solving,
+/// since the comparator reads node data by index
+/// while the group generator only tracks positions.
+pub fn sort_cmp<T, I, P, C, F>(nodes: &mut [T], parent: P, children: C, cmp: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          F: Fn(&T, &T) -> Ordering
+{
+    let mut buf: Vec<I> = vec![];
     for i in 0..nodes.len() {
-        for p in parents(&mut nodes[i]) {
-            *p = gen[*p];
-        }
-        for ch in children(&mut nodes[i]) {
-            *ch = gen[*ch]
-        }
+        buf.clear();
+        buf.extend_from_slice(children(&mut nodes[i]));
+        buf.sort_by(|a, b| cmp(&nodes[a.to_usize()], &nodes[b.to_usize()]));
+        children(&mut nodes[i]).copy_from_slice(&buf);
     }
+    sort(nodes, parent, children)
+}
 
-    for i in 0..nodes.len() {
-        while gen[i] != i {
-            let j = gen[i];
-            nodes.swap(i, j);
-            gen.swap(i, j);
-        }
+/// Sorts the children of every node by index, without moving node data or changing parents.
+///
+/// This restores the order of siblings when only the children lists have been shuffled,
+/// while every child is already stored after its parent.
+/// It is cheaper than `sort`, but does not move nodes that are stored before their parent.
+pub fn normalize_children<T, I, C>(nodes: &mut [T], children: C)
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    for node in nodes.iter_mut() {
+        children(node).sort_unstable_by_key(|ch| ch.to_usize());
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Returns `true` if the nodes are already sorted,
+/// such that `sort` and `sort_dag` would leave them unchanged.
+///
+/// This checks in a single pass that every child is greater than their parent,
+/// and every sibling is greater than previous siblings.
+pub fn is_sorted<T, I, C>(nodes: &mut [T], children: C) -> bool
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    nodes.iter_mut().enumerate().all(|(i, node)| in_order(i, children(node)))
+}
 
-    #[derive(PartialEq, Debug)]
-    struct Node {
-        val: u32,
-        parent: Option<usize>,
-        children: Vec<usize>,
+/// Returned by `first_violation` for a relation that `sort` would fix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The `parent` lists `child` as a child, but `child` is stored before `parent`.
+    ChildBeforeParent {
+        /// The node that lists the child.
+        parent: usize,
+        /// The child.
+        child: usize,
+    },
+    /// The `parent` lists `earlier` before `later`, but `later` is stored before `earlier`.
+    SiblingOutOfOrder {
+        /// The node that lists the siblings.
+        parent: usize,
+        /// The sibling listed first.
+        earlier: usize,
+        /// The sibling listed next.
+        later: usize,
+    },
+}
+
+/// Returns the first relation that is out of order, for explaining what `sort` would fix.
+///
+/// The nodes are scanned in order, checking every child against the node listing it
+/// and against the previous sibling.
+/// Returns `None` if the nodes are sorted, which is the same as `is_sorted`.
+pub fn first_violation<T, I, C>(nodes: &mut [T], children: C) -> Option<Violation>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    for (i, node) in nodes.iter_mut().enumerate() {
+        let mut prev: Option<usize> = None;
+        for ch in children(node).iter() {
+            let ch = ch.to_usize();
+            if ch < i {
+                return Some(Violation::ChildBeforeParent {parent: i, child: ch});
+            }
+            match prev {
+                Some(earlier) if earlier > ch => {
+                    return Some(Violation::SiblingOutOfOrder {parent: i, earlier, later: ch});
+                }
+                _ => prev = Some(ch),
+            }
+        }
     }
+    None
+}
 
-    #[test]
-    fn empty() {
-        let mut nodes: Vec<Node> = vec![];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(nodes.len(), 0);
+/// Panics unless every child is stored after its parent,
+/// and every sibling is stored after previous siblings.
+///
+/// This checks both the parent and the children of every node,
+/// and is intended for tests and debug assertions.
+/// A tree that passes this check is left unchanged by `sort`.
+/// See the `invariants` module for checks that return the violation instead of panicking.
+pub fn assert_sorted_invariants<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    if let Err(err) = invariants::check_child_after_parent(nodes, &parent, &children) {
+        panic!("{}", err);
     }
+    if let Err(err) = invariants::check_sibling_order(nodes, &children) {
+        panic!("{}", err);
+    }
+}
 
-    #[test]
-    fn one() {
-        let mut nodes: Vec<Node> = vec![Node {
-            val: 0,
-            parent: None,
-            children: vec![],
-        }];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![Node {
-                val: 0,
-                parent: None,
-                children: vec![]
-            }]
-        );
+/// Returns `true` if `visit` would perform no swaps on node `i` with the identity generator.
+///
+/// Since sibling order is transitive, it is sufficient to check adjacent siblings,
+/// and the parent against the first child.
+fn in_order<I: Index>(i: usize, children: &[I]) -> bool {
+    match children.first() {
+        Some(first) if i > first.to_usize() => false,
+        _ => children.windows(2).all(|w| w[0].to_usize() <= w[1].to_usize()),
+    }
+}
+
+/// Returned when sorting fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SortError {
+    /// The solving phase did not reach a fixpoint within the allowed number of passes.
+    ///
+    /// Returned by `try_sort` when the children contain a cycle.
+    NotConverged {
+        /// The number of passes of the solving phase, including the pass that gave up.
+        passes: usize,
+        /// The generator when giving up, which is a permutation of the node indices.
+        ///
+        /// This shows how far the solving phase got,
+        /// and can be passed to `sort_resume` to continue from there.
+        partial: Vec<usize>,
+    },
+    /// A node refers to an index out of bounds.
+    OutOfBounds(BadIndex),
+    /// A node is referenced by more than one parent.
+    DuplicateParent(DuplicateParentError),
+    /// A node lists itself as a child.
+    SelfChild(usize),
+}
+
+/// The same algorithm as `sort`, but returns an error instead of panicking
+/// or running in an infinite loop on invalid input.
+///
+/// This checks for indices out of bounds, nodes listing themselves as children
+/// and nodes with more than one parent,
+/// and limits the solving phase to the number of swaps needed to sort any valid tree.
+/// The number of nodes must fit in the index type.
+/// The nodes are left untouched when an error is returned.
+pub fn try_sort<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Result<(), SortError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    if let Some(err) = find_bad_index(nodes, &parent, &children) {
+        return Err(SortError::OutOfBounds(err));
+    }
+    if let Some(node) = find_self_child(nodes, &children) {
+        return Err(SortError::SelfChild(node));
+    }
+    if let Some(err) = find_duplicate_parent(nodes, &children) {
+        return Err(SortError::DuplicateParent(err));
+    }
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let max_swaps = max_swaps(nodes.len());
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, max_swaps)
+            .map_err(|passes| SortError::NotConverged {passes, partial: gen.clone()})?;
+    if passes == 1 {
+        // The nodes are already sorted.
+        return Ok(());
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+    Ok(())
+}
+
+/// The same algorithm as `sort`, but gives up after `max_passes` passes of the solving phase.
+///
+/// Returns the number of passes used, including the final pass that detected no changes.
+/// This can be used to fail fast when a node is referenced by more than one parent.
+///
+/// The nodes are left untouched when an error is returned.
+pub fn sort_bounded<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+    max_passes: usize,
+) -> Result<usize, SortError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    sort_with_gen(nodes, &parent, &children, &mut gen, max_passes)
+}
+
+/// Sorts a tree starting from the identity generator `gen`.
+///
+/// The nodes are left untouched when an error is returned.
+fn sort_with_gen<S, I, P, C>(
+    nodes: &mut S,
+    parent: &P,
+    children: &C,
+    gen: &mut [usize],
+    max_passes: usize,
+) -> Result<usize, SortError>
+    where S: Storage + ?Sized,
+          I: Index,
+          P: Fn(&mut S::Output) -> &mut Option<I>,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    // This problem can be solved efficiently using Group Theory.
+    // This avoids the need for cloning nodes into a new array,
+    // while performing the minimum work to get a normalized tree.
+    //
+    // Create a group generator that is modified by swapping to find a solution.
+    // The group generator keeps track of indices, such that child-parent relations
+    // do not have to change until later.
+    //
+    // Use the order in the generator to detect whether a swap has been performed.
+    // The condition for swapping `a, b` is `gen[a] > gen[b]`.
+    let (passes, _) = solve(nodes, children, gen, SortOrder::Ascending, max_passes, usize::MAX)
+        .map_err(|passes| SortError::NotConverged {passes, partial: gen.to_vec()})?;
+    if passes == 1 {
+        // The first pass made no changes, so the nodes are already sorted.
+        // Skip updating indices and swapping nodes, which would do nothing.
+        return Ok(passes);
+    }
+
+    // Update the tree data with the new indices from the generator.
+    // Do this before performing the actual swapping,
+    // since the generator maps from old indices to new indices.
+    rewrite(nodes, parent, children, gen);
+
+    retrace_with(nodes, gen, |_, _| {});
+    Ok(passes)
+}
+
+/// Statistics about the work performed by `sort_counting`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SortStats {
+    /// The number of passes in the solving phase,
+    /// including the final pass that detected no changes.
+    pub passes: usize,
+    /// The number of swaps of the generator in the solving phase.
+    pub generator_swaps: usize,
+    /// The number of swaps of node data in the retrace phase.
+    pub data_swaps: usize,
+}
+
+/// The same algorithm as `sort`, but counts the work performed.
+///
+/// This can be used to measure how close to sorted a tree was.
+pub fn sort_counting<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> SortStats
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, generator_swaps) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    rewrite_indices(nodes, &parent, &children, &gen);
+    let data_swaps = retrace(nodes, &mut gen);
+    SortStats {passes, generator_swaps, data_swaps}
+}
+
+/// Statistics about how far nodes moved, returned by `sort_movement`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MovementStats {
+    /// The sum of the distances between the old and new index of every node.
+    pub total_displacement: usize,
+    /// The largest distance between the old and new index of a node.
+    pub max_displacement: usize,
+    /// The number of nodes that kept their index.
+    pub unmoved: usize,
+}
+
+/// The same algorithm as `sort`, but measures how far the nodes moved.
+///
+/// This can be used to compare strategies for constructing trees,
+/// since the algorithm tends to keep nodes close to their original location.
+pub fn sort_movement<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> MovementStats
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+        .expect("solving phase has no limit");
+    let mut stats = MovementStats::default();
+    for (i, &j) in gen.iter().enumerate() {
+        let d = i.abs_diff(j);
+        stats.total_displacement += d;
+        stats.max_displacement = stats.max_displacement.max(d);
+        if d == 0 {
+            stats.unmoved += 1;
+        }
+    }
+    if stats.unmoved < nodes.len() {
+        rewrite_indices(nodes, &parent, &children, &gen);
+        retrace(nodes, &mut gen);
+    }
+    stats
+}
+
+/// The same algorithm as `sort`, where the tree structure and the data of the nodes
+/// are stored in separate slices.
+///
+/// The permutation is computed from `structure` and applied to both slices in lockstep,
+/// such that `data[i]` stays with `structure[i]`.
+///
+/// Panics if the slices have different lengths.
+pub fn sort_split<S, D, I, P, C>(structure: &mut [S], data: &mut [D], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut S) -> &mut Option<I>,
+          C: Fn(&mut S) -> &mut [I]
+{
+    assert_eq!(structure.len(), data.len(), "expected structure and data of the same length");
+    let mut gen: Vec<usize> = (0..structure.len()).collect();
+    let (passes, _) =
+        solve(structure, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(structure, &parent, &children, &gen);
+    retrace_with(structure, &mut gen, |i, j| data.swap(i, j));
+}
+
+/// The same algorithm as `sort`, where the parents and children are stored in separate slices.
+///
+/// Both slices are permuted together and their indices are rewritten,
+/// such that `parents[i]` and `children[i]` belong to the same node.
+///
+/// Panics if the slices have different lengths.
+pub fn sort_columns<I: Index>(parents: &mut [Option<I>], children: &mut [Vec<I>]) {
+    assert_eq!(parents.len(), children.len(), "expected parents and children of the same length");
+    let mut gen: Vec<usize> = (0..children.len()).collect();
+    let (passes, _) =
+        solve(children, &|c: &mut Vec<I>| &mut c[..], &mut gen, SortOrder::Ascending,
+            usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    for p in parents.iter_mut() {
+        *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+    }
+    for ch in children.iter_mut().flat_map(|c| c.iter_mut()) {
+        *ch = I::from_usize(gen[ch.to_usize()]);
+    }
+    retrace_with(children, &mut gen, |i, j| parents.swap(i, j));
+}
+
+/// The parent of a node and the range of its children in a shared pool, see `sort_pooled`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    /// The parent of the node.
+    pub parent: Option<usize>,
+    /// The range in the pool where the children of the node are stored.
+    pub children: Range<usize>,
+}
+
+/// The same algorithm as `sort`, where the values and links are stored in separate slices
+/// and the children of all nodes are stored in a shared pool.
+///
+/// Both slices are permuted together, such that `values[i]` and `links[i]` belong
+/// to the same node, and the indices in the parents and the pool are rewritten.
+/// The pool itself is not reordered, since every range moves with its link.
+/// The ranges must not overlap, and parts of the pool outside the ranges are left unchanged.
+///
+/// Panics if the slices have different lengths.
+pub fn sort_pooled<T>(values: &mut [T], links: &mut [Link], child_pool: &mut [usize]) {
+    assert_eq!(values.len(), links.len(), "expected values and links of the same length");
+    let mut gen: Vec<usize> = (0..links.len()).collect();
+    let mut scratch = vec![];
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for (i, link) in links.iter().enumerate() {
+            let children = &child_pool[link.children.clone()];
+            changed |= visit(i, children, &mut gen, SortOrder::Ascending, &mut scratch) > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    for link in links.iter_mut() {
+        link.parent = link.parent.map(|p| gen[p]);
+        for ch in &mut child_pool[link.children.clone()] {
+            *ch = gen[*ch];
+        }
+    }
+    retrace_with(links, &mut gen, |i, j| values.swap(i, j));
+}
+
+/// The same algorithm as `sort`, but also returns the swaps of node data in the retrace phase.
+///
+/// Performing the swaps `data.swap(i, j)` in order moves the data of the original nodes
+/// to their sorted positions, and performing them in reverse order restores the original order.
+/// The indices stored in nodes are updated separately, so only data without indices,
+/// or a parallel array, can be replayed this way.
+pub fn sort_record<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<(usize, usize)>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    let mut log = vec![];
+    if passes == 1 {
+        // The nodes are already sorted.
+        return log;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace_with(nodes, &mut gen, |i, j| log.push((i, j)));
+    log
+}
+
+/// The layout after `sort_live`, for truncating or recycling the dead nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactResult {
+    /// The number of live nodes, which are stored at `0..live_len`.
+    pub live_len: usize,
+    /// The indices of the dead nodes after sorting, which are free to reuse.
+    pub freed: Vec<usize>,
+}
+
+/// Sorts the live nodes of a forest at the front, and moves dead nodes to the end.
+///
+/// Nodes where `is_live` returns `false` are tombstones, such as freed slots of an arena.
+/// Their parents and children are ignored and left untouched,
+/// and they do not participate in ordering, so they are stored after the live nodes
+/// in the order they had before sorting.
+/// The live nodes are sorted like `sort`, and must only refer to other live nodes.
+///
+/// Returns the number of live nodes and the indices of the dead nodes at the end.
+pub fn sort_live<T, I, P, C, L>(nodes: &mut [T], parent: P, children: C, is_live: L)
+    -> CompactResult
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          L: Fn(&T) -> bool
+{
+    let n = nodes.len();
+    let live: Vec<bool> = nodes.iter().map(is_live).collect();
+    let mut gen: Vec<usize> = (0..n).collect();
+    let mut scratch = vec![];
+    loop {
+        let mut changed = false;
+        for (i, node) in nodes.iter_mut().enumerate() {
+            if live[i] {
+                changed |= visit(i, children(node), &mut gen, SortOrder::Ascending,
+                    &mut scratch) > 0;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // Keep the order of live nodes from the generator, and put dead nodes after them.
+    let mut order: Vec<usize> = (0..n).filter(|&i| live[i]).collect();
+    order.sort_unstable_by_key(|&i| gen[i]);
+    let live_len = order.len();
+    let result = CompactResult {live_len, freed: (live_len..n).collect()};
+    order.extend((0..n).filter(|&i| !live[i]));
+    for (k, &i) in order.iter().enumerate() {
+        gen[i] = k;
+    }
+    if gen.iter().enumerate().all(|(i, &g)| g == i) {
+        // The nodes are already sorted.
+        return result;
+    }
+
+    for (i, node) in nodes.iter_mut().enumerate() {
+        if live[i] {
+            let p = parent(node);
+            *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+            for ch in children(node) {
+                *ch = I::from_usize(gen[ch.to_usize()]);
+            }
+        }
+    }
+    retrace(nodes, &mut gen);
+    result
+}
+
+/// The same algorithm as `sort`, but returns where every node moved.
+///
+/// The returned vector maps every old index to its new index,
+/// such that the node stored at `i` before sorting is stored at `map[i]` after sorting.
+/// This is the same direction as the generator from `sort_permutation`,
+/// and can be used to update external tables of indices in a single pass:
+///
+/// ```text
+/// for pos in table.values_mut() {
+///     *pos = map[*pos];
+/// }
+/// ```
+pub fn sort_with_map<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return gen;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    let map = gen.clone();
+    retrace(nodes, &mut gen);
+    map
+}
+
+/// The same algorithm as `sort`, but also returns the size of every subtree.
+///
+/// The sizes are indexed by the new positions of the nodes,
+/// and count a node with all its descendants, such that leaves have size 1.
+/// In a forest, the size of every root covers only its own tree.
+/// Since children are stored after their parents after sorting,
+/// the sizes are computed in a single pass over the sorted nodes in reverse.
+pub fn sort_with_sizes<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort(nodes, &parent, &children);
+    subtree_sizes(nodes, &parent)
+}
+
+/// Returns the size of every subtree of sorted nodes.
+fn subtree_sizes<T, I, P>(nodes: &mut [T], parent: &P) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>
+{
+    let mut sizes = vec![1; nodes.len()];
+    for i in (0..nodes.len()).rev() {
+        if let Some(p) = *parent(&mut nodes[i]) {
+            sizes[p.to_usize()] += sizes[i];
+        }
+    }
+    sizes
+}
+
+/// Translates old indices to new indices after sorting, returned by `sort_renumber`.
+///
+/// The permutation is shared, so cloning is cheap.
+/// Stable Rust does not allow implementing `Fn` for a struct,
+/// so use `get`, indexing or `as_fn` to translate indices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Renumber {
+    map: Rc<[usize]>,
+}
+
+impl Renumber {
+    /// Returns the new index of the node that was stored at `old`.
+    pub fn get(&self, old: usize) -> usize {self.map[old]}
+
+    /// Returns a closure translating old indices to new indices.
+    pub fn as_fn(&self) -> impl Fn(usize) -> usize + '_ {
+        move |old| self.map[old]
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {self.map.len()}
+
+    /// Returns `true` if there are no nodes.
+    pub fn is_empty(&self) -> bool {self.map.is_empty()}
+}
+
+impl core::ops::Index<usize> for Renumber {
+    type Output = usize;
+    fn index(&self, old: usize) -> &usize {&self.map[old]}
+}
+
+/// The same algorithm as `sort`, but returns a `Renumber` for translating old indices lazily.
+///
+/// This avoids updating stored indices eagerly when most of them are never used,
+/// see `sort_with_map`.
+pub fn sort_renumber<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Renumber
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    Renumber {map: sort_with_map(nodes, parent, children).into()}
+}
+
+/// The same algorithm as `sort`, but returns the `(old, new)` indices of the nodes that moved.
+///
+/// The pairs are in ascending order of old indices, and nodes that kept their index are left out,
+/// so the result is empty when the nodes are already sorted.
+/// This is `sort_with_map` without the unchanged entries,
+/// for updating external tables of indices when few nodes move.
+pub fn sort_changes<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<(usize, usize)>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort_with_map(nodes, parent, children).into_iter().enumerate()
+        .filter(|&(old, new)| old != new)
+        .collect()
+}
+
+/// The same algorithm as `sort`, but calls `on_move(i, j)` for every swap in the retrace phase,
+/// right after the nodes at `i` and `j` have been swapped.
+///
+/// Performing `data.swap(i, j)` in every call keeps external storage,
+/// such as a parallel array or a buffer on another device, in lockstep with the nodes,
+/// without collecting the swaps like `sort_record` does.
+/// After a call, the node at `j` is in its sorted position and is never moved again,
+/// so every `j` is reported at most once, and `i < j`.
+/// The calls come in ascending order of `i`, where the same `i` is reported repeatedly
+/// while a cycle of moves is resolved, until the node that belongs at `i` arrives there.
+/// A cycle of `k` nodes takes `k - 1` calls, and nodes that keep their index are not reported,
+/// so there are no calls when the nodes are already sorted.
+pub fn sort_on_move<T, I, P, C, F>(nodes: &mut [T], parent: P, children: C, on_move: F)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          F: FnMut(usize, usize)
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace_with(nodes, &mut gen, on_move);
+}
+
+/// Sorts a tree such that every subtree is stored contiguously,
+/// and returns the range of indices covering every node and its descendants.
+///
+/// The ranges are indexed by the new positions of the nodes,
+/// such that node `j` is a descendant of node `i` when `ranges[i].contains(&j)`.
+///
+/// `sort` does not guarantee that subtrees are contiguous,
+/// since a node might be stored between a node and the children of that node,
+/// for example a later sibling.
+/// Therefore, after sorting, the nodes are moved into depth first order,
+/// which still has every child greater than their parent,
+/// and every sibling greater than previous siblings.
+pub fn sort_with_ranges<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<Range<usize>>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort(nodes, &parent, &children);
+    compact_subtrees(nodes, &parent, &children);
+    let sizes = subtree_sizes(nodes, &parent);
+    sizes.iter().enumerate().map(|(i, &size)| i..i + size).collect()
+}
+
+/// Moves nodes into depth first order, such that every subtree is stored contiguously.
+///
+/// Roots are visited in the order they are stored, and children in the order they are listed.
+/// The result has every child greater than their parent,
+/// and every sibling greater than previous siblings,
+/// so this can be used after `sort` when descendants are queried by index ranges.
+/// Sorted nodes are only moved when some subtree is not contiguous.
+///
+/// Panics if the parents contain a cycle.
+pub fn compact_subtrees<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let mut gen = vec![0; n];
+    let mut next = 0;
+    let mut stack: Vec<usize> = vec![];
+    for root in 0..n {
+        if parent(&mut nodes[root]).is_some() {
+            continue;
+        }
+        stack.push(root);
+        while let Some(i) = stack.pop() {
+            gen[i] = next;
+            next += 1;
+            stack.extend(children(&mut nodes[i]).iter().rev().map(|ch| ch.to_usize()));
+        }
+    }
+    assert_eq!(next, n, "parent links contain a cycle");
+    if gen.iter().enumerate().all(|(i, &g)| g == i) {
+        // The subtrees are already contiguous.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// Sorts a tree in breadth first order, storing all roots first, then all nodes at depth 1,
+/// and so on.
+///
+/// Within every depth, the nodes are ordered by the order of their parents,
+/// and siblings keep the order they are listed in.
+/// Roots are ordered as they are stored.
+/// This still has every child greater than their parent,
+/// and every sibling greater than previous siblings,
+/// but unlike `sort`, subtrees are not stored together.
+///
+/// Panics if the parents contain a cycle.
+pub fn sort_bfs<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    // The nodes in breadth first order, used as a queue.
+    let mut order: Vec<usize> = (0..n).filter(|&i| parent(&mut nodes[i]).is_none()).collect();
+    let mut next = 0;
+    while next < order.len() {
+        let i = order[next];
+        order.extend(children(&mut nodes[i]).iter().map(|ch| ch.to_usize()));
+        next += 1;
+    }
+    assert_eq!(order.len(), n, "parent links contain a cycle");
+    if order.iter().enumerate().all(|(i, &j)| i == j) {
+        // The nodes are already sorted.
+        return;
+    }
+    let mut gen = invert(&order);
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// The same algorithm as `sort`, but also returns the depth of every node.
+///
+/// The depths are indexed by the new positions of the nodes, where roots have depth 0.
+/// Since parents are stored before their children after sorting,
+/// the depths are computed in a single pass over the sorted nodes.
+pub fn sort_with_depths<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort(nodes, &parent, &children);
+    let mut depths = vec![0; nodes.len()];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        if let Some(p) = *parent(node) {
+            depths[i] = depths[p.to_usize()] + 1;
+        }
+    }
+    depths
+}
+
+/// The same algorithm as `sort`, but orders the roots of a forest as listed in `roots`.
+///
+/// Every listed root is stored before the roots listed after it,
+/// as if the roots were the children of a virtual root node.
+/// Roots that are not listed are ordered as by `sort`.
+/// Only the roots are ordered relative to each other,
+/// so the nodes of different trees might still be interleaved.
+///
+/// Panics if a listed root has a parent.
+pub fn sort_forest<T, I, P, C>(nodes: &mut [T], parent: P, children: C, roots: &[usize])
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    for &root in roots {
+        assert!(parent(&mut nodes[root]).is_none(), "root `{}` has a parent", root);
+    }
+
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let mut scratch = vec![];
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for (i, node) in nodes.iter_mut().enumerate() {
+            changed |= visit(i, children(node), &mut gen, SortOrder::Ascending, &mut scratch) > 0;
+        }
+        changed |= visit_siblings(roots, &mut gen) > 0;
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// The same algorithm as `sort`, but with extra ordering constraints between nodes.
+///
+/// Every pair `(a, b)` in `extra` means that node `a` is stored before node `b`.
+/// When the constraints contradict each other or the tree,
+/// `SortError::NotConverged` is returned after the number of swaps
+/// that would be sufficient to satisfy any consistent set of constraints.
+/// When a pair refers to a node out of bounds, `SortError::OutOfBounds` is returned,
+/// with the other node of the pair as the node referring to the index.
+///
+/// The nodes are left untouched when an error is returned.
+pub fn sort_with_constraints<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+    extra: &[(usize, usize)],
+) -> Result<(), SortError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    for &(a, b) in extra {
+        if b >= n {
+            return Err(SortError::OutOfBounds(BadIndex {node: a, index: b}));
+        }
+        if a >= n {
+            return Err(SortError::OutOfBounds(BadIndex {node: b, index: a}));
+        }
+    }
+    let mut gen: Vec<usize> = (0..n).collect();
+    let mut scratch = vec![];
+    let max_swaps = max_swaps(n);
+    let mut swaps = 0;
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut k = 0;
+        for (i, node) in nodes.iter_mut().enumerate() {
+            k += visit(i, children(node), &mut gen, SortOrder::Ascending, &mut scratch);
+        }
+        for &(a, b) in extra {
+            if gen[a] > gen[b] {
+                gen.swap(a, b);
+                k += 1;
+            }
+        }
+        if k == 0 {
+            break;
+        }
+        swaps += k;
+        if swaps > max_swaps {
+            return Err(SortError::NotConverged {passes, partial: gen});
+        }
+    }
+    if passes > 1 {
+        rewrite_indices(nodes, &parent, &children, &gen);
+        retrace(nodes, &mut gen);
+    }
+    Ok(())
+}
+
+/// The same algorithm as `sort`, but keeps the relative order of the roots.
+///
+/// Nodes without a parent are stored in the same order as before sorting,
+/// while their descendants are reordered around them.
+/// Since roots are not children of any node, this only conflicts with the tree
+/// when a root is listed as a child, in which case `SortError::NotConverged` is returned.
+///
+/// The nodes are left untouched when an error is returned.
+pub fn sort_stable_roots<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    -> Result<(), SortError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let roots = roots(nodes, &parent);
+    let extra: Vec<(usize, usize)> = roots.windows(2).map(|w| (w[0], w[1])).collect();
+    sort_with_constraints(nodes, parent, children, &extra)
+}
+
+/// Returned by `sort_checked` when a node is referenced by more than one parent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateParentError {
+    /// The shared node.
+    pub node: usize,
+    /// The first parent that lists the node as a child.
+    pub first_parent: usize,
+    /// The second parent that lists the node as a child.
+    pub second_parent: usize,
+}
+
+/// The same algorithm as `sort`, but checks first that
+/// every node is referenced by maximum one parent.
+///
+/// This catches shared nodes in a single pass,
+/// instead of risking an infinite loop in the solving phase.
+/// The nodes are left untouched when an error is returned.
+pub fn sort_checked<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+) -> Result<(), DuplicateParentError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    if let Some(err) = find_duplicate_parent(nodes, &children) {
+        return Err(err);
+    }
+    sort(nodes, parent, children);
+    Ok(())
+}
+
+/// Returns the first node that lists itself as a child.
+///
+/// Such a node is not a valid tree, and makes the node look like it has two parents.
+pub fn find_self_child<T, I, C>(nodes: &mut [T], children: C) -> Option<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    nodes.iter_mut().enumerate()
+        .position(|(i, node)| children(node).iter().any(|ch| ch.to_usize() == i))
+}
+
+/// The same algorithm as `sort`, but first removes children that refer to the node itself.
+pub fn sort_drop_self_children<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut Vec<I>
+{
+    for (i, node) in nodes.iter_mut().enumerate() {
+        children(node).retain(|ch| ch.to_usize() != i);
+    }
+    sort(nodes, parent, |n| &mut children(n)[..]);
+}
+
+/// In debug builds, panics if a node is listed as a child by more than one parent.
+fn debug_check_shared<S, I, C>(nodes: &mut S, children: &C)
+    where S: Storage + ?Sized,
+          I: Index,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    if cfg!(debug_assertions) {
+        // Shared nodes make the solving phase loop forever, so fail loudly during development.
+        if let Some(err) = find_duplicate_parent(nodes, children) {
+            panic!("node `{}` is a child of both `{}` and `{}`, use `sort_dag` for shared nodes",
+                err.node, err.first_parent, err.second_parent);
+        }
+    }
+}
+
+/// Returns the first node that is listed as a child by two different parents.
+fn find_duplicate_parent<S, I, C>(nodes: &mut S, children: &C) -> Option<DuplicateParentError>
+    where S: Storage + ?Sized,
+          I: Index,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    let mut owner: Vec<Option<usize>> = vec![None; nodes.len()];
+    for i in 0..nodes.len() {
+        for ch in children(&mut nodes[i]) {
+            let ch = ch.to_usize();
+            match owner[ch] {
+                Some(p) if p != i => {
+                    return Some(DuplicateParentError {
+                        node: ch,
+                        first_parent: p,
+                        second_parent: i,
+                    });
+                }
+                _ => owner[ch] = Some(i),
+            }
+        }
+    }
+    None
+}
+
+/// Returned by `check_consistency` when parents and children disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The `parent` lists `child` as a child, but `child` has a different parent or no parent.
+    WrongParent {
+        /// The node that lists the child.
+        parent: usize,
+        /// The child.
+        child: usize,
+    },
+    /// The parent of `child` is `parent`, but `parent` does not list `child` as a child.
+    MissingChild {
+        /// The parent of the child.
+        parent: usize,
+        /// The child.
+        child: usize,
+    },
+}
+
+/// Checks that every child has its parent set to the node that lists it,
+/// and that every node with a parent is listed as a child of that parent.
+///
+/// Panics if an index is out of bounds, see `sort_validated`.
+pub fn check_consistency<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+) -> Result<(), Inconsistency>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let parents: Vec<Option<usize>> = nodes.iter_mut()
+        .map(|node| parent(node).map(|p| p.to_usize()))
+        .collect();
+    let mut listed = vec![false; nodes.len()];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        for ch in children(node).iter() {
+            let ch = ch.to_usize();
+            if parents[ch] != Some(i) {
+                return Err(Inconsistency::WrongParent {parent: i, child: ch});
+            }
+            listed[ch] = true;
+        }
+    }
+    for (i, p) in parents.into_iter().enumerate() {
+        match p {
+            Some(p) if !listed[i] => return Err(Inconsistency::MissingChild {parent: p, child: i}),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returned by `sort_validated` when a node refers to an index out of bounds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BadIndex {
+    /// The node that refers to the index.
+    pub node: usize,
+    /// The index out of bounds.
+    pub index: usize,
+}
+
+/// The same algorithm as `sort`, but checks first that
+/// every parent and child index is less than the number of nodes.
+///
+/// This reports stale indices in a single pass,
+/// instead of panicking in the middle of sorting.
+/// The nodes are left untouched when an error is returned.
+pub fn sort_validated<T, I, P, C>(nodes: &mut [T], parent: P, children: C) -> Result<(), BadIndex>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    if let Some(err) = find_bad_index(nodes, &parent, &children) {
+        return Err(err);
+    }
+    sort(nodes, parent, children);
+    Ok(())
+}
+
+/// Returns the first parent or child index that is out of bounds.
+fn find_bad_index<T, I, P, C>(nodes: &mut [T], parent: &P, children: &C) -> Option<BadIndex>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    for (i, node) in nodes.iter_mut().enumerate() {
+        let p = parent(node).map(|p| p.to_usize());
+        let index = p.into_iter()
+            .chain(children(node).iter().map(|ch| ch.to_usize()))
+            .find(|&index| index >= n);
+        if let Some(index) = index {
+            return Some(BadIndex {node: i, index});
+        }
+    }
+    None
+}
+
+/// Returned by `validate_tree` when the parent links contain a cycle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParentCycle {
+    /// The nodes of the cycle, where every node is the parent of the previous node.
+    pub nodes: Vec<usize>,
+}
+
+/// Checks that following parent links from any node reaches a root.
+///
+/// A single-parent tree has no cycles in its parent links by definition,
+/// so a cycle is a bug in how the tree was built, which would make sorting loop forever.
+/// Every node is walked at most twice, using a stamp per node to detect revisits.
+///
+/// Panics if a parent is out of bounds.
+pub fn validate_tree<T, I, P>(nodes: &mut [T], parent: P) -> Result<(), ParentCycle>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>
+{
+    const UNVISITED: usize = 0;
+    const DONE: usize = usize::MAX;
+    // Every walk stamps the nodes it visits with its own number.
+    let mut stamp = vec![UNVISITED; nodes.len()];
+    for i in 0..nodes.len() {
+        let mut j = i;
+        while stamp[j] != DONE {
+            if stamp[j] == i + 1 {
+                // The walk returned to a node it visited before.
+                let mut cycle = vec![j];
+                let mut k = parent(&mut nodes[j]).unwrap().to_usize();
+                while k != j {
+                    cycle.push(k);
+                    k = parent(&mut nodes[k]).unwrap().to_usize();
+                }
+                return Err(ParentCycle {nodes: cycle});
+            }
+            stamp[j] = i + 1;
+            match *parent(&mut nodes[j]) {
+                Some(p) => j = p.to_usize(),
+                None => break,
+            }
+        }
+        // Mark the walk as reaching a root.
+        let mut j = i;
+        while stamp[j] != DONE {
+            stamp[j] = DONE;
+            match *parent(&mut nodes[j]) {
+                Some(p) => j = p.to_usize(),
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the indices of all nodes without a parent, in increasing order.
+///
+/// Every root starts a separate tree of the forest.
+pub fn roots<T, I, P>(nodes: &mut [T], parent: P) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>
+{
+    nodes.iter_mut().enumerate()
+        .filter_map(|(i, node)| if parent(node).is_none() {Some(i)} else {None})
+        .collect()
+}
+
+/// The number of roots of nodes, returned by `classify`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Exactly one node has no parent.
+    Tree,
+    /// The number of nodes without a parent, which is not 1.
+    /// Nodes without any nodes are a forest of no trees.
+    Forest(usize),
+    /// There are nodes, but every node has a parent,
+    /// so the parent links contain a cycle and `sort` would not terminate.
+    Rootless,
+}
+
+/// Classifies nodes as a single tree, a forest or nodes without roots, by counting the roots.
+///
+/// This is a cheap check to run on untrusted input before sorting.
+/// Nodes with roots might still contain a cycle, see `validate_tree`.
+pub fn classify<T, I, P>(nodes: &mut [T], parent: P) -> Topology
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>
+{
+    let n_roots = nodes.iter_mut().map(|node| parent(node).is_none()).filter(|&root| root).count();
+    match n_roots {
+        0 if !nodes.is_empty() => Topology::Rootless,
+        1 => Topology::Tree,
+        n_roots => Topology::Forest(n_roots),
+    }
+}
+
+/// Returns the root of the tree that every node belongs to.
+///
+/// Panics if the parent links contain a cycle.
+pub fn forest_partition<T, I, P>(nodes: &mut [T], parent: P) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>
+{
+    const UNKNOWN: usize = usize::MAX;
+    let mut root = vec![UNKNOWN; nodes.len()];
+    let mut path: Vec<usize> = vec![];
+    for i in 0..nodes.len() {
+        // Walk up until a node with a known root is found.
+        let mut j = i;
+        while root[j] == UNKNOWN {
+            assert!(path.len() < nodes.len(), "parent links contain a cycle");
+            path.push(j);
+            match *parent(&mut nodes[j]) {
+                Some(p) => j = p.to_usize(),
+                None => {
+                    root[j] = j;
+                    break;
+                }
+            }
+        }
+        let r = root[j];
+        for k in path.drain(..) {
+            root[k] = r;
+        }
+    }
+    root
+}
+
+/// Appends a subtree as the last child of `attach_to`, and sorts the nodes.
+///
+/// The indices in `sub` are relative to `sub`, with exactly one root.
+/// They are offset by the current number of nodes,
+/// and the root is linked to `attach_to` in both directions.
+/// The `children` accessor returns a `Vec` to add the root as a child of `attach_to`.
+///
+/// Panics if `attach_to` is out of bounds, or if `sub` does not have exactly one root.
+pub fn append_subtree<T, I, P, C>(
+    nodes: &mut Vec<T>,
+    mut sub: Vec<T>,
+    attach_to: usize,
+    parent: P,
+    children: C,
+)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut Vec<I>
+{
+    assert!(attach_to < nodes.len(), "node `{}` is out of bounds", attach_to);
+    let offset = nodes.len();
+    let mut root = None;
+    for (i, node) in sub.iter_mut().enumerate() {
+        let p = parent(node);
+        match *p {
+            Some(q) => *p = Some(I::from_usize(q.to_usize() + offset)),
+            None => {
+                assert!(root.is_none(), "expected exactly one root in subtree");
+                root = Some(i + offset);
+                *p = Some(I::from_usize(attach_to));
+            }
+        }
+        for ch in children(node).iter_mut() {
+            *ch = I::from_usize(ch.to_usize() + offset);
+        }
+    }
+    let root = root.expect("expected exactly one root in subtree");
+    children(&mut nodes[attach_to]).push(I::from_usize(root));
+    nodes.append(&mut sub);
+    sort(nodes, parent, |n| &mut children(n)[..]);
+}
+
+/// Copies the subtree starting at `root` into new nodes, and sorts them.
+///
+/// The indices of the copies are renumbered to refer to the new nodes,
+/// and the parent of the root is set to `None`,
+/// such that the result is a standalone tree with the root at index 0.
+/// The original nodes are left unchanged.
+///
+/// Panics if `root` is out of bounds.
+pub fn extract_subtree<T, I, P, C>(nodes: &mut [T], root: usize, parent: P, children: C) -> Vec<T>
+    where T: Clone,
+          I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    assert!(root < nodes.len(), "node `{}` is out of bounds", root);
+    let sub = descendants(nodes, root, &children);
+    // Map the index of every node in the subtree to its index in the copy.
+    let mut new = vec![0; nodes.len()];
+    for (k, &i) in sub.iter().enumerate() {
+        new[i] = k;
+    }
+    let mut res: Vec<T> = sub.iter().map(|&i| nodes[i].clone()).collect();
+    for node in res.iter_mut() {
+        let p = parent(node);
+        *p = p.map(|p| I::from_usize(new[p.to_usize()]));
+        for ch in children(node) {
+            *ch = I::from_usize(new[ch.to_usize()]);
+        }
+    }
+    *parent(&mut res[new[root]]) = None;
+    sort(&mut res, parent, children);
+    res
+}
+
+/// Sorts the subtree starting at `root`, leaving all other nodes in place.
+///
+/// The nodes of the subtree are reordered among the positions they already occupy,
+/// such that every child is greater than their parent,
+/// and every sibling is greater than previous siblings.
+///
+/// Indices are updated in the nodes of the subtree and in the parent of `root`.
+/// Since a node in a tree has at most one parent,
+/// no other node outside the subtree can point into it.
+pub fn sort_subtree<T, I, P, C>(nodes: &mut [T], root: usize, parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let subtree = descendants(nodes, root, &children);
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let mut scratch = vec![];
+    loop {
+        let mut changed = false;
+        for &i in &subtree {
+            changed |= visit(i, children(&mut nodes[i]), &mut gen, SortOrder::Ascending,
+                &mut scratch) > 0;
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // The generator is the identity outside the subtree.
+    let root_parent = parent(&mut nodes[root]).map(|p| p.to_usize());
+    for &i in subtree.iter().chain(root_parent.iter()) {
+        let node = &mut nodes[i];
+        if i != root {
+            let p = parent(node);
+            *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+        }
+        for ch in children(node) {
+            *ch = I::from_usize(gen[ch.to_usize()])
+        }
+    }
+
+    for &i in &subtree {
+        while gen[i] != i {
+            let j = gen[i];
+            nodes.swap(i, j);
+            gen.swap(i, j);
+        }
+    }
+}
+
+/// Sorts the tree starting at the top-level `root` of a forest, leaving all other trees in place.
+///
+/// The nodes of the tree are reordered among the positions they already occupy,
+/// see `sort_subtree`.
+/// When the positions of several trees interleave, the nodes of other trees keep their positions,
+/// so the sorted tree might not be stored contiguously.
+///
+/// Panics if `root` has a parent.
+pub fn sort_one<T, I, P, C>(nodes: &mut [T], parent: P, children: C, root: usize)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    assert!(parent(&mut nodes[root]).is_none(), "root `{}` has a parent", root);
+    sort_subtree(nodes, root, parent, children)
+}
+
+/// Makes `new_root` the root of its tree, then sorts the nodes.
+///
+/// The links along the path from `new_root` to the old root are reversed,
+/// such that every node on the path becomes the last child of the node below it.
+/// Other trees of a forest are not changed, except by sorting,
+/// and nothing is reversed when `new_root` is already a root.
+///
+/// Panics if the parents contain a cycle.
+pub fn reroot<T, I, P, C>(nodes: &mut [T], new_root: usize, parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut Vec<I>
+{
+    let mut path = vec![new_root];
+    let mut i = new_root;
+    while let Some(p) = *parent(&mut nodes[i]) {
+        assert!(path.len() <= nodes.len(), "parent links contain a cycle");
+        i = p.to_usize();
+        path.push(i);
+    }
+    for w in path.windows(2) {
+        let (ch, p) = (w[0], w[1]);
+        let list = children(&mut nodes[p]);
+        if let Some(k) = list.iter().position(|x| x.to_usize() == ch) {
+            list.remove(k);
+        }
+        children(&mut nodes[ch]).push(I::from_usize(p));
+        *parent(&mut nodes[p]) = Some(I::from_usize(ch));
+    }
+    *parent(&mut nodes[new_root]) = None;
+    sort(nodes, parent, |n| &mut children(n)[..]);
+}
+
+/// Returns `root` and all its descendants, in ascending order.
+fn descendants<T, I, C>(nodes: &mut [T], root: usize, children: &C) -> Vec<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut visited = vec![false; nodes.len()];
+    let mut res = vec![];
+    let mut stack = vec![root];
+    visited[root] = true;
+    while let Some(i) = stack.pop() {
+        res.push(i);
+        for ch in children(&mut nodes[i]) {
+            let ch = ch.to_usize();
+            if !visited[ch] {
+                visited[ch] = true;
+                stack.push(ch);
+            }
+        }
+    }
+    res.sort_unstable();
+    res
+}
+
+/// The same algorithm as `sort`, but for Directed Acyclic Graphs (DAGs),
+/// encoded as trees with shared nodes.
+///
+/// WARNING: To avoid an infinite loop, one must be careful about the order of children.
+/// E.g. if `A` has children `C, B` and `B` has child `C`, then the tree is not a DAG.
+/// This is because the order of children is preserved after sorting.
+pub fn sort_dag<T, I, P, C>(nodes: &mut [T], parents: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    update_dag(nodes, &parents, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// The same algorithm as `sort_dag`, but first removes duplicate parents and children.
+///
+/// Only the first occurrence of an index is kept in every list.
+/// This avoids sorting repeated children, which could otherwise require
+/// a child to be both before and after its siblings.
+/// Since the new indices are unique, the lists stay free of duplicates after sorting.
+pub fn sort_dag_dedup<T, I, P, C>(nodes: &mut [T], parents: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Vec<I>,
+          C: Fn(&mut T) -> &mut Vec<I>
+{
+    let mut seen = vec![false; nodes.len()];
+    for node in nodes.iter_mut() {
+        dedup(parents(node), &mut seen);
+        dedup(children(node), &mut seen);
+    }
+    sort_dag(nodes, |n| &mut parents(n)[..], |n| &mut children(n)[..]);
+}
+
+/// Removes all but the first occurrence of every index in a list.
+///
+/// The flags in `seen` are set for the indices in the list, and cleared before returning.
+fn dedup<I: Index>(list: &mut Vec<I>, seen: &mut [bool]) {
+    list.retain(|i| !core::mem::replace(&mut seen[i.to_usize()], true));
+    for i in list.iter() {
+        seen[i.to_usize()] = false;
+    }
+}
+
+/// The same algorithm as `sort_dag`, but first normalizes the parents and children.
+///
+/// Growable lists make it possible to remove edges that would confuse the sort:
+///
+/// - Indices that are out of bounds
+/// - Duplicate indices, keeping only the first occurrence in every list
+/// - Dangling edges, where a node lists a child that does not list the node as a parent,
+///   or a parent that does not list the node as a child
+///
+/// Use `sort_dag` when the lists are already normalized.
+pub fn sort_dag_vec<T, I, P, C>(nodes: &mut [T], parents: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Vec<I>,
+          C: Fn(&mut T) -> &mut Vec<I>
+{
+    let n = nodes.len();
+    let mut seen = vec![false; n];
+    for node in nodes.iter_mut() {
+        parents(node).retain(|p| p.to_usize() < n);
+        children(node).retain(|ch| ch.to_usize() < n);
+        dedup(parents(node), &mut seen);
+        dedup(children(node), &mut seen);
+    }
+
+    // Collect the edges seen from both sides as `(parent, child)` pairs.
+    let mut down = vec![];
+    let mut up = vec![];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        down.extend(children(node).iter().map(|ch| (i, ch.to_usize())));
+        up.extend(parents(node).iter().map(|p| (p.to_usize(), i)));
+    }
+    down.sort_unstable();
+    up.sort_unstable();
+    for (i, node) in nodes.iter_mut().enumerate() {
+        children(node).retain(|ch| up.binary_search(&(i, ch.to_usize())).is_ok());
+        parents(node).retain(|p| down.binary_search(&(p.to_usize(), i)).is_ok());
+    }
+    sort_dag(nodes, |n| &mut parents(n)[..], |n| &mut children(n)[..]);
+}
+
+/// The same algorithm as `sort_dag`, but also returns the level of every node.
+///
+/// The level is the length of the longest path from a node without parents,
+/// indexed by the new positions of the nodes.
+/// Nodes on the same level do not depend on each other,
+/// such that tasks can be executed level by level.
+pub fn sort_dag_levels<T, I, P, C>(nodes: &mut [T], parents: P, children: C) -> Vec<usize>
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort_dag(nodes, &parents, &children);
+    let mut levels = vec![0; nodes.len()];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        // Parents are stored before their children after sorting.
+        levels[i] = parents(node).iter().map(|p| levels[p.to_usize()] + 1).max().unwrap_or(0);
+    }
+    levels
+}
+
+/// Sorts a DAG with `sort_dag`, then writes the nodes in sorted order with `encode`.
+///
+/// Since the sorted order is the same for DAGs that are equal after sorting,
+/// this gives the same bytes for the same DAG, e.g. for content addressing.
+/// The encoding is left to `encode`, such that any format can be used.
+/// Stops at the first error returned by `encode`.
+#[cfg(feature = "std")]
+pub fn write_canonical<T, I, P, C, W, E>(
+    nodes: &mut [T],
+    parents: P,
+    children: C,
+    w: &mut W,
+    mut encode: E,
+) -> std::io::Result<()>
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I],
+          W: std::io::Write,
+          E: FnMut(&T, &mut W) -> std::io::Result<()>
+{
+    sort_dag(nodes, parents, children);
+    for node in nodes.iter() {
+        encode(node, w)?;
+    }
+    Ok(())
+}
+
+/// Returned by `try_sort_dag` when the tree is not a DAG.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError {
+    /// A node that participates in a cycle.
+    pub node: usize,
+}
+
+/// The same algorithm as `sort_dag`, but returns an error instead of
+/// running in an infinite loop when the tree is not a DAG.
+///
+/// Every swap in the solving phase fixes an inversion relative to some topological order,
+/// so a DAG with `n` nodes never needs more than `n * (n - 1) / 2` swaps.
+/// When this limit is exceeded, the ordering constraints are searched for a cycle.
+///
+/// The nodes are left untouched when an error is returned.
+pub fn try_sort_dag<T, I, P, C>(nodes: &mut [T], parents: P, children: C) -> Result<(), CycleError>
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let mut gen: Vec<usize> = (0..n).collect();
+    match solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, max_swaps(n)) {
+        Err(_) => {
+            let cycle = find_cycle(nodes, &children)
+                .expect("exceeded the swap limit without a cycle");
+            return Err(CycleError {node: cycle[0]});
+        }
+        // The nodes are already sorted.
+        Ok((1, _)) => return Ok(()),
+        Ok(_) => {}
+    }
+    update_dag(nodes, &parents, &children, &gen);
+    retrace(nodes, &mut gen);
+    Ok(())
+}
+
+/// The same algorithm as `sort_dag`, but returns a cycle as a path
+/// instead of running in an infinite loop when the tree is not a DAG.
+///
+/// The path lists the nodes of the cycle in the order they are constrained,
+/// where every node must come before the next, starting and ending with the same node.
+/// The constraints are that every child comes after its parent,
+/// and every sibling comes after previous siblings.
+/// The cycle is searched for when the swaps exceed the limit described in `try_sort_dag`.
+///
+/// The nodes are left untouched when an error is returned.
+pub fn sort_dag_checked<T, I, P, C>(nodes: &mut [T], parents: P, children: C)
+    -> Result<(), Vec<usize>>
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let mut gen: Vec<usize> = (0..n).collect();
+    match solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, max_swaps(n)) {
+        Err(_) => {
+            let mut cycle = find_cycle(nodes, &children)
+                .expect("exceeded the swap limit without a cycle");
+            cycle.push(cycle[0]);
+            return Err(cycle);
+        }
+        // The nodes are already sorted.
+        Ok((1, _)) => return Ok(()),
+        Ok(_) => {}
+    }
+    update_dag(nodes, &parents, &children, &gen);
+    retrace(nodes, &mut gen);
+    Ok(())
+}
+
+/// Returns `true` if `sort_dag` terminates for the nodes, without running it.
+///
+/// The order of children implies that every child comes after its parent,
+/// and that every sibling comes after previous siblings.
+/// This checks that these constraints contain no cycle,
+/// such as in the example with `B` ordering `D` before `C` while `D` is a child of `C`.
+/// The parents are not needed, since the constraints only depend on the children.
+///
+/// Panics if a child index is out of bounds.
+pub fn dag_is_consistent<T, I, C>(nodes: &mut [T], children: C) -> bool
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    find_cycle(nodes, &children).is_none()
+}
+
+/// Returned by `from_adjacency` when the edges contain a cycle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotADag {
+    /// A node that participates in a cycle.
+    pub node: usize,
+}
+
+/// The parents and children of every node, returned by `from_adjacency`.
+pub type Adjacency = (Vec<Vec<usize>>, Vec<Vec<usize>>);
+
+/// Builds the parents and children of `n` nodes from a list of `(parent, child)` edges,
+/// in the form that `sort_dag` expects.
+///
+/// The parents of every node are stored in the order of the edges.
+/// The children of every node are ordered by a topological order of the edges,
+/// such that the order of siblings never contradicts the edges,
+/// and `sort_dag` is guaranteed to terminate.
+/// Duplicate edges are ignored.
+///
+/// Panics if an edge is out of bounds.
+pub fn from_adjacency(n: usize, edges: &[(usize, usize)]) -> Result<Adjacency, NotADag> {
+    let mut parents: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut children: Vec<Vec<usize>> = vec![vec![]; n];
+    for &(a, b) in edges {
+        assert!(a < n && b < n, "edge `({}, {})` is out of bounds", a, b);
+        if !children[a].contains(&b) {
+            children[a].push(b);
+            parents[b].push(a);
+        }
+    }
+
+    // Find a topological order of the edges.
+    let mut indegree: Vec<usize> = parents.iter().map(|p| p.len()).collect();
+    let mut order: Vec<usize> = (0..n).filter(|&i| indegree[i] == 0).collect();
+    let mut k = 0;
+    while let Some(&a) = order.get(k) {
+        k += 1;
+        for &b in &children[a] {
+            indegree[b] -= 1;
+            if indegree[b] == 0 {
+                order.push(b);
+            }
+        }
+    }
+    if order.len() < n {
+        // Every node left over has a parent that is left over,
+        // so walking parents `n` times ends on a cycle.
+        let mut node = (0..n).find(|&i| indegree[i] > 0).unwrap();
+        for _ in 0..n {
+            node = *parents[node].iter().find(|&&p| indegree[p] > 0).unwrap();
+        }
+        return Err(NotADag {node});
+    }
+
+    let mut rank = vec![0; n];
+    for (r, &i) in order.iter().enumerate() {
+        rank[i] = r;
+    }
+    for list in &mut children {
+        list.sort_by_key(|&b| rank[b]);
+    }
+    Ok((parents, children))
+}
+
+/// Computes the permutation that `sort` or `sort_dag` would apply to the nodes,
+/// without moving node data or changing any indices.
+///
+/// The returned generator maps every old index to its new index.
+/// Use `apply_permutation` to reorder the nodes or any parallel array.
+pub fn sort_permutation<T, I, C>(nodes: &mut [T], children: C) -> Vec<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    solve_generator(nodes, children)
+}
+
+/// Returns the original indices of the nodes in the order they would have after sorting,
+/// without moving node data or changing any indices.
+///
+/// This is the same as `sort_inverse_permutation`, for visiting nodes in sorted order:
+///
+/// ```rust
+/// use tree_mem_sort::topological_order;
+///
+/// struct Node {
+///     val: u32,
+///     children: Vec<usize>,
+/// }
+///
+/// let mut nodes = vec![
+///     Node {val: 1, children: vec![]},
+///     Node {val: 0, children: vec![0]},
+/// ];
+/// let vals: Vec<u32> = topological_order(&mut nodes, |n| &mut n.children)
+///     .into_iter().map(|i| nodes[i].val).collect();
+/// assert_eq!(vals, vec![0, 1]);
+/// ```
+pub fn topological_order<T, I, C>(nodes: &mut [T], children: C) -> Vec<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    sort_inverse_permutation(nodes, children)
+}
+
+/// Runs the solving phase of `sort`, which swaps the group generator until it reaches a fixpoint.
+///
+/// The returned generator maps every old index to its new index.
+/// Together with `rewrite_indices` and `retrace`, this performs the same steps as `sort`:
+///
+/// ```rust
+/// use tree_mem_sort::{retrace, rewrite_indices, solve_generator};
+///
+/// struct Node {
+///     val: u32,
+///     parent: Option<usize>,
+///     children: Vec<usize>,
+/// }
+///
+/// let mut nodes = vec![
+///     Node {val: 1, parent: Some(1), children: vec![]},
+///     Node {val: 0, parent: None, children: vec![0]},
+/// ];
+/// let mut gen = solve_generator(&mut nodes, |n| &mut n.children);
+/// assert_eq!(gen, vec![1, 0]);
+/// rewrite_indices(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &gen);
+/// retrace(&mut nodes, &mut gen);
+/// assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1]);
+/// assert_eq!(nodes[0].children, vec![1]);
+/// assert_eq!(nodes[1].parent, Some(0));
+/// ```
+pub fn solve_generator<T, I, C>(nodes: &mut [T], children: C) -> Vec<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+        .expect("solving phase has no limit");
+    gen
+}
+
+/// Returns the minimum number of swaps that move the nodes into the order of `sort`.
+///
+/// A permutation of `n` elements with `c` cycles, counting fixed points,
+/// needs at least `n - c` transpositions, since every transposition
+/// changes the number of cycles by one.
+/// This is also the number of swaps performed by `retrace`,
+/// which places at least one node where it belongs with every swap
+/// and two with the last swap of every cycle.
+pub fn swap_distance<T, I, C>(nodes: &mut [T], children: C) -> usize
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let gen = solve_generator(nodes, children);
+    let n = gen.len();
+    let mut seen = vec![false; n];
+    let mut cycles = 0;
+    for start in 0..n {
+        if seen[start] {
+            continue;
+        }
+        cycles += 1;
+        let mut i = start;
+        while !seen[i] {
+            seen[i] = true;
+            i = gen[i];
+        }
+    }
+    n - cycles
+}
+
+/// Computes the permutation that `sort_dag` would apply to `n` nodes,
+/// where the tree is stored as a list of `(parent, child)` edges.
+///
+/// The children of a node are ordered as their edges appear in the list.
+/// The endpoints of the edges are rewritten to the new indices,
+/// and the returned generator maps every old index to its new index.
+/// Use `apply_permutation` to reorder the node data.
+/// To only compute the permutation, pass a copy of the edges.
+///
+/// Panics if an endpoint is out of bounds, see `try_sort_edges`.
+/// If the edges contain a cycle, this runs in an infinite loop,
+/// so use `try_sort_edges` for edges built from external data.
+pub fn sort_edges(n: usize, edges: &mut [(usize, usize)]) -> Vec<usize> {
+    sort_edges_with_limit(n, edges, usize::MAX).expect("solving phase has no limit")
+}
+
+/// Sorts a list of edges, see `sort_edges`.
+///
+/// The edges are left untouched when the solving phase needs more than `max_swaps` swaps.
+fn sort_edges_with_limit(n: usize, edges: &mut [(usize, usize)], max_swaps: usize)
+    -> Result<Vec<usize>, EdgeError>
+{
+    // Group the children by parent, preserving the order of edges.
+    let mut offsets = vec![0; n + 1];
+    for &(a, _) in edges.iter() {
+        offsets[a + 1] += 1;
+    }
+    for i in 0..n {
+        offsets[i + 1] += offsets[i];
+    }
+    let mut targets = vec![0; edges.len()];
+    let mut next = offsets.clone();
+    for &(a, b) in edges.iter() {
+        targets[next[a]] = b;
+        next[a] += 1;
+    }
+
+    let mut gen: Vec<usize> = (0..n).collect();
+    let mut scratch = vec![];
+    let mut passes = 0;
+    let mut swaps: usize = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        for i in 0..n {
+            let children = &targets[offsets[i]..offsets[i + 1]];
+            let k = visit(i, children, &mut gen, SortOrder::Ascending, &mut scratch);
+            if k > 0 {
+                swaps = swaps.saturating_add(k);
+                if swaps > max_swaps {
+                    return Err(EdgeError::Cycle);
+                }
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    if passes == 1 {
+        // The edges are already sorted.
+        return Ok(gen);
+    }
+    for (a, b) in edges.iter_mut() {
+        *a = gen[*a];
+        *b = gen[*b];
+    }
+    Ok(gen)
+}
+
+/// Returned by `try_sort_edges` when the edges can not be sorted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EdgeError {
+    /// An endpoint of an edge is out of bounds.
+    OutOfBounds {
+        /// The index of the edge in the list.
+        edge: usize,
+        /// The endpoint that is out of bounds.
+        endpoint: usize,
+    },
+    /// The edges contain a cycle.
+    Cycle,
+}
+
+/// The same as `sort_edges`, but returns an error if an endpoint is out of bounds
+/// or if the edges contain a cycle.
+///
+/// This is useful for edges built from external data.
+/// A cycle is detected when the solving phase needs more swaps than any DAG, see `try_sort_dag`.
+/// The edges are left untouched when an error is returned.
+pub fn try_sort_edges(n: usize, edges: &mut [(usize, usize)]) -> Result<Vec<usize>, EdgeError> {
+    for (k, &(a, b)) in edges.iter().enumerate() {
+        if let Some(endpoint) = [a, b].iter().cloned().find(|&e| e >= n) {
+            return Err(EdgeError::OutOfBounds {edge: k, endpoint});
+        }
+    }
+    sort_edges_with_limit(n, edges, max_swaps(n))
+}
+
+/// Renders a forest as nested brackets, such as `12(2, 6(3, 2))`, for debugging.
+///
+/// The roots are rendered in the order they would have after sorting, separated by commas.
+/// Children are rendered in the order they are listed, without moving node data.
+pub fn to_bracket_string<T, I, P, C, F>(nodes: &mut [T], parent: P, children: C, fmt: F) -> String
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I],
+          F: Fn(&T) -> String
+{
+    let gen = solve_generator(nodes, &children);
+    let mut roots: Vec<usize> = roots(nodes, parent);
+    roots.sort_by_key(|&r| gen[r]);
+
+    let mut s = String::new();
+    // Every entry is a node and the position of the next child to render.
+    let mut stack: Vec<(usize, usize)> = vec![];
+    for (k, &root) in roots.iter().enumerate() {
+        if k > 0 {
+            s.push_str(", ");
+        }
+        s.push_str(&fmt(&nodes[root]));
+        stack.push((root, 0));
+        while let Some((i, j)) = stack.pop() {
+            let children = children(&mut nodes[i]);
+            match children.get(j) {
+                Some(ch) => {
+                    s.push_str(if j == 0 {"("} else {", "});
+                    let ch = ch.to_usize();
+                    stack.push((i, j + 1));
+                    stack.push((ch, 0));
+                    s.push_str(&fmt(&nodes[ch]));
+                }
+                None if j > 0 => s.push(')'),
+                None => {}
+            }
+        }
+    }
+    s
+}
+
+/// Renders a forest as a Graphviz DOT digraph, for debugging and documentation.
+///
+/// Every node is rendered at its current index, labeled with the index and `label`,
+/// with edges from every parent to its children in the order they are listed.
+/// Rendering before and after sorting shows how the nodes were reordered.
+pub fn to_dot<T, I, C, F>(nodes: &mut [T], children: C, label: F) -> String
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I],
+          F: Fn(usize, &T) -> String
+{
+    let mut s = String::from("digraph {\n");
+    for (i, node) in nodes.iter().enumerate() {
+        let label = label(i, node).replace('\\', "\\\\").replace('"', "\\\"");
+        s.push_str(&alloc::format!("    {} [label=\"{}: {}\"];\n", i, i, label));
+    }
+    for (i, node) in nodes.iter_mut().enumerate() {
+        for ch in children(node).iter() {
+            s.push_str(&alloc::format!("    {} -> {};\n", i, ch.to_usize()));
+        }
+    }
+    s.push_str("}\n");
+    s
+}
+
+/// Computes the inverse of the permutation that `sort` or `sort_dag` would apply to the nodes,
+/// without moving node data or changing any indices.
+///
+/// The returned array maps every new index to its old index,
+/// such that the node at new position `i` was originally at position `inv[i]`.
+pub fn sort_inverse_permutation<T, I, C>(nodes: &mut [T], children: C) -> Vec<usize>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    invert(&sort_permutation(nodes, children))
+}
+
+/// Feeds the sorted form of a tree to `hasher`, without moving node data or changing any indices.
+///
+/// The value and the new child indices of every node are hashed in the order that `sort` gives.
+/// Trees that are equal after sorting produce the same hash, regardless of their layout.
+/// Since `sort` preserves the original order where possible,
+/// trees with the same shape and values might still be different after sorting,
+/// for example when two subtrees are interleaved differently.
+///
+/// Numbers are written to the hasher as 64 bit little-endian bytes,
+/// such that the hash does not depend on the platform unless the hasher does.
+pub fn structural_hash<T, I, C, V, H>(nodes: &mut [T], children: C, value: V, hasher: &mut H)
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I],
+          V: Fn(&T) -> u64,
+          H: Hasher
+{
+    // Fixed width and byte order, since `write_usize` and `write_u64` depend on the platform.
+    let mut write = |x: u64| hasher.write(&x.to_le_bytes());
+    let gen = solve_generator(nodes, &children);
+    write(nodes.len() as u64);
+    for i in invert(&gen) {
+        let node = &mut nodes[i];
+        write(value(node));
+        let children = children(node);
+        write(children.len() as u64);
+        for ch in children.iter() {
+            write(gen[ch.to_usize()] as u64);
+        }
+    }
+}
+
+/// Returns `true` if two trees are equal after sorting,
+/// without moving node data or changing any indices.
+///
+/// The trees are compared node by node in the order that `sort` gives,
+/// comparing the values returned by `value` and the new child indices.
+/// Since nodes store indices that depend on the layout, the nodes themselves are not compared.
+/// Forests are compared the same way, and trees with different numbers of nodes are not equal.
+pub fn tree_eq<T, I, C, V, U>(a: &mut [T], b: &mut [T], children: C, value: V) -> bool
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I],
+          V: Fn(&T) -> U,
+          U: PartialEq
+{
+    if a.len() != b.len() {
+        return false;
+    }
+    let gen_a = solve_generator(a, &children);
+    let gen_b = solve_generator(b, &children);
+    invert(&gen_a).into_iter().zip(invert(&gen_b)).all(|(i, j)| {
+        if value(&a[i]) != value(&b[j]) {
+            return false;
+        }
+        let children_a = children(&mut a[i]);
+        let children_b = children(&mut b[j]);
+        children_a.len() == children_b.len() &&
+        children_a.iter().zip(children_b.iter())
+            .all(|(x, y)| gen_a[x.to_usize()] == gen_b[y.to_usize()])
+    })
+}
+
+/// Returns the inverse of a permutation.
+fn invert(perm: &[usize]) -> Vec<usize> {
+    let mut inv = vec![0; perm.len()];
+    for (i, &j) in perm.iter().enumerate() {
+        inv[j] = i;
+    }
+    inv
+}
+
+/// Moves every element `data[i]` to `data[perm[i]]`.
+///
+/// This uses the same in-place swapping as the retrace phase of `sort`.
+/// Panics if `perm` is not a permutation of `0..data.len()`.
+pub fn apply_permutation<T>(data: &mut [T], perm: &[usize]) {
+    assert!(is_permutation(perm, data.len()), "expected a permutation of `0..{}`", data.len());
+    permute_in_place(data, &mut perm.to_vec());
+}
+
+/// Moves every element `data[perm[i]]` back to `data[i]`, undoing `apply_permutation`.
+///
+/// With the generator from `sort_permutation`, this restores the layout from before sorting.
+/// Indices stored in the nodes are not changed.
+/// To restore them, call `rewrite_indices` with the inverse of the generator first.
+///
+/// Panics if `gen` is not a permutation of `0..data.len()`.
+pub fn unsort<T>(data: &mut [T], gen: &[usize]) {
+    assert!(is_permutation(gen, data.len()), "expected a permutation of `0..{}`", data.len());
+    permute_in_place(data, &mut invert(gen));
+}
+
+/// Moves every element `data[i]` to `data[perm[i]]`, without allocating memory.
+///
+/// This uses the same in-place swapping as the retrace phase of `sort`,
+/// tracking in `perm` where each displaced element belongs.
+/// The permutation is consumed and left as the identity permutation.
+///
+/// `perm` must be a permutation of `0..data.len()`, such as one returned by `sort_permutation`.
+/// Otherwise, this might panic or never return.
+/// Use `apply_permutation` to check the permutation and keep it unchanged.
+pub fn permute_in_place<T>(data: &mut [T], perm: &mut [usize]) {
+    debug_assert!(
+        is_permutation(perm, data.len()),
+        "expected a permutation of `0..{}`",
+        data.len()
+    );
+    retrace(data, perm);
+}
+
+/// Returns `true` if `perm` contains every index in `0..n` exactly once.
+fn is_permutation(perm: &[usize], n: usize) -> bool {
+    if perm.len() != n {
+        return false;
+    }
+    let mut seen = vec![false; n];
+    for &i in perm {
+        if i >= n || seen[i] {
+            return false;
+        }
+        seen[i] = true;
+    }
+    true
+}
+
+/// The maximum number of generator swaps required to sort a DAG with `n` nodes.
+fn max_swaps(n: usize) -> usize {
+    n.saturating_mul(n.saturating_sub(1)) / 2
+}
+
+/// Swaps the generator until every child is greater than its parent,
+/// and every sibling is greater than previous siblings.
+///
+/// After a pass with few swaps, the remaining passes only visit the nodes near swaps,
+/// see `worklist`.
+///
+/// Returns the number of passes and swaps, or the number of passes as an error
+/// if the fixpoint was not reached within `max_passes` passes and `max_swaps` swaps.
+fn solve<S, I, C>(
+    nodes: &mut S,
+    children: &C,
+    gen: &mut [usize],
+    order: SortOrder,
+    max_passes: usize,
+    max_swaps: usize,
+) -> Result<(usize, usize), usize>
+    where S: Storage + ?Sized,
+          I: Index,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    let mut scratch = vec![];
+    let mut passes = 0;
+    let mut swaps: usize = 0;
+    loop {
+        if passes == max_passes {
+            return Err(passes);
+        }
+        passes += 1;
+        let before = swaps;
+        let mut changed = false;
+        for i in 0..nodes.len() {
+            let n = visit(i, children(&mut nodes[i]), gen, order, &mut scratch);
+            if n > 0 {
+                swaps = swaps.saturating_add(n);
+                if swaps > max_swaps {
+                    return Err(passes);
+                }
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok((passes, swaps));
+        }
+        if nodes.len() > SMALL && (swaps - before).saturating_mul(worklist::SPARSE) < nodes.len() {
+            // Few nodes are out of order, so continue by visiting only the nodes near swaps,
+            // which needs memory.
+            return worklist::solve(nodes, children, gen, order, (max_passes, max_swaps),
+                (passes, swaps));
+        }
+    }
+}
+
+/// Checks the children of node `i` against their parent and each other,
+/// swapping the generator where the order is violated.
+///
+/// The `scratch` buffer is reused between visits of nodes with many children,
+/// so the callers keep one for all visits of a sort.
+///
+/// Returns the number of swaps.
+fn visit<I: Index>(
+    i: usize,
+    children: &[I],
+    gen: &mut [usize],
+    order: SortOrder,
+    scratch: &mut Vec<usize>,
+) -> usize {
+    if children.is_empty() {
+        // Leaves have nothing to check.
+        return 0;
+    }
+    if children.len() >= siblings::WIDE {
+        if let Some(swaps) = siblings::visit(i, children, gen, order, scratch) {
+            return swaps;
+        }
+    }
+    visit_pairs(i, children, gen, order)
+}
+
+/// Checks the children of node `i` by comparing all pairs of children.
+///
+/// Returns the number of swaps.
+fn visit_pairs<I: Index>(i: usize, children: &[I], gen: &mut [usize], order: SortOrder) -> usize {
+    visit_pairs_with(i, children, gen, order, |_, _| {})
+}
+
+/// Checks the children of node `i` by comparing all pairs of children,
+/// calling `on_swap` for every swap of the generator.
+///
+/// Returns the number of swaps.
+fn visit_pairs_with<I, F>(
+    i: usize,
+    children: &[I],
+    gen: &mut [usize],
+    order: SortOrder,
+    mut on_swap: F,
+) -> usize
+    where I: Index,
+          F: FnMut(usize, usize)
+{
+    let mut swaps = 0;
+    for j in 0..children.len() {
+        let a = children[j].to_usize();
+        // Store child after its parent.
+        if gen[i] > gen[a] {
+            gen.swap(i, a);
+            on_swap(i, a);
+            swaps += 1;
+        }
+        // Check all pairs of children.
+        for b in &children[j + 1..] {
+            let b = b.to_usize();
+            let (x, y) = match order {
+                SortOrder::Ascending => (a, b),
+                SortOrder::Descending => (b, a),
+            };
+            // Store children in sorted order.
+            if gen[x] > gen[y] {
+                gen.swap(x, y);
+                on_swap(x, y);
+                swaps += 1;
+            }
+        }
+    }
+    swaps
+}
+
+/// Checks all pairs of siblings without a parent,
+/// swapping the generator where the order is violated.
+///
+/// Returns the number of swaps.
+fn visit_siblings(siblings: &[usize], gen: &mut [usize]) -> usize {
+    let mut swaps = 0;
+    for (j, &a) in siblings.iter().enumerate() {
+        for &b in &siblings[j + 1..] {
+            if gen[a] > gen[b] {
+                gen.swap(a, b);
+                swaps += 1;
+            }
+        }
+    }
+    swaps
+}
+
+/// Updates the parent and children of a tree with the new indices from the generator.
+///
+/// This is the middle phase of `sort`, between `solve_generator` and `retrace`.
+/// It must be called before `retrace`, since the generator maps from old indices to new indices.
+pub fn rewrite_indices<T, I, P, C>(nodes: &mut [T], parent: P, children: C, gen: &[usize])
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    rewrite(nodes, &parent, &children, gen);
+}
+
+/// Updates the parent and children of a tree in any storage, see `rewrite_indices`.
+fn rewrite<S, I, P, C>(nodes: &mut S, parent: &P, children: &C, gen: &[usize])
+    where S: Storage + ?Sized,
+          I: Index,
+          P: Fn(&mut S::Output) -> &mut Option<I>,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    for i in 0..nodes.len() {
+        let node = &mut nodes[i];
+        let p = parent(node);
+        *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+        for ch in children(node) {
+            *ch = I::from_usize(gen[ch.to_usize()])
+        }
+    }
+}
+
+/// Updates the parents and children of a DAG with the new indices from the generator.
+fn update_dag<T, I, P, C>(nodes: &mut [T], parents: &P, children: &C, gen: &[usize])
+    where I: Index,
+          P: Fn(&mut T) -> &mut [I],
+          C: Fn(&mut T) -> &mut [I]
+{
+    for node in nodes.iter_mut() {
+        for p in parents(node) {
+            *p = I::from_usize(gen[p.to_usize()]);
+        }
+        for ch in children(node) {
+            *ch = I::from_usize(gen[ch.to_usize()])
+        }
+    }
+}
+
+/// Swaps nodes using the group generator as guide.
+///
+/// This is the last phase of `sort`, which moves every node `nodes[i]` to `nodes[gen[i]]`.
+/// It works for any permutation of `0..nodes.len()`, not only generators from `solve_generator`.
+/// The generator is left as the identity permutation.
+///
+/// Returns the number of swaps.
+pub fn retrace<T>(nodes: &mut [T], gen: &mut [usize]) -> usize {
+    let mut swaps = 0;
+    retrace_with(nodes, gen, |_, _| swaps += 1);
+    swaps
+}
+
+/// Moves nodes using the group generator as guide, following every cycle of the permutation.
+///
+/// The result is the same as `retrace`, but every node is moved exactly once,
+/// plus one move into and out of a temporary per cycle,
+/// where `retrace` performs a swap of three moves for every node out of place.
+/// This reduces the memory moved for large node types,
+/// although access to arbitrary locations might still dominate for large arrays.
+/// An inverse of the generator is allocated to find the node that belongs at each position.
+/// The generator is left as the identity permutation.
+///
+/// Panics if `gen` is not a permutation of `0..nodes.len()`.
+pub fn retrace_cycles<T>(nodes: &mut [T], gen: &mut [usize]) {
+    let n = nodes.len();
+    // Moving nodes out of bounds or twice would be unsound, so this is always checked.
+    assert!(is_permutation(gen, n), "expected a permutation of `0..{}`", n);
+    let mut inv = invert(gen);
+    let ptr = nodes.as_mut_ptr();
+    for start in 0..n {
+        if inv[start] == start {
+            continue;
+        }
+        // Move the first node of the cycle out, leaving a hole that is filled
+        // by the node that belongs there, until the hole reaches the end of the cycle.
+        //
+        // SAFETY: All indices are in bounds since `inv` is a permutation of `0..n`.
+        // Every position in the cycle is read once and written once,
+        // and no code that could panic runs while a node is duplicated.
+        unsafe {
+            let tmp = core::ptr::read(ptr.add(start));
+            let mut hole = start;
+            loop {
+                let k = inv[hole];
+                inv[hole] = hole;
+                if k == start {
+                    core::ptr::write(ptr.add(hole), tmp);
+                    break;
+                }
+                core::ptr::copy_nonoverlapping(ptr.add(k), ptr.add(hole), 1);
+                hole = k;
+            }
+        }
+    }
+    for (i, g) in gen.iter_mut().enumerate() {
+        *g = i;
+    }
+}
+
+/// The same algorithm as `sort`, but uses `retrace_cycles` to move the nodes.
+///
+/// This moves less memory for large node types,
+/// see `examples/large.rs` for comparing the two on your machine.
+pub fn sort_large<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    let (passes, _) =
+        solve(nodes, &children, &mut gen, SortOrder::Ascending, usize::MAX, usize::MAX)
+            .expect("solving phase has no limit");
+    if passes == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace_cycles(nodes, &mut gen);
+}
+
+/// Swaps nodes using the group generator as guide, calling `on_swap` for every swap.
+fn retrace_with<S, F>(nodes: &mut S, gen: &mut [usize], mut on_swap: F)
+    where S: Storage + ?Sized,
+          F: FnMut(usize, usize)
+{
+    // When swapping has been performed, update the generator to keep track of state.
+    // This is because multiple swaps sharing elements might require multiple steps.
+    //
+    // The order which swaps are retraced might be different than the solving phase:
+    //
+    // `a, b, c` => `a, (c, b)` => `(c, a), b` => `c, a, b` (solving phase)
+    // `c, a, b` => `(b), a, (c)` => `(a, b), c` => `a, b, c` (retrace phase)
+    //
+    // However, since the generator solution is produced by swapping operations,
+    // it is guaranteed to be restorable to the identity generator when retracing.
+    // In fact, it works for any permutation of the identity generator.
+    //
+    // There is no need to loop more than once because each index is stored uniquely by lookup,
+    // such that if `g[i] = i` then there exists no `j != i` such that `g[j] = i`.
+    //
+    // Retrace by putting nodes where they belong (`g[i]`).
+    // Once a node is put where it belongs, no need further work is needed for `g[i]`.
+    // It means that the problem has been reduced from size `n` to `n-1`.
+    //
+    // However, in order to put one node from `i` to where it belongs `g[i]`,
+    // one must also keep track of the node who occupied the previous `j = g[i]`.
+    // Not only keep track of the node data, but where it belongs `g[j]` too.
+    //
+    // Fortunately, the old position (`i`) is free as a temporary location.
+    // This is why `i` and `g[i]` are swapped.
+    // The same swap is done in the group generator to track where the new node belongs:
+    // `gen[j] => gen[i]`
+    //
+    // This procedure is repeated for the new node `i`,
+    // putting it where it belongs `g[i]`, and in return receive a new task `gen[j] => gen[i]`.
+    // The task is repeated until `i` belongs to where it should be,
+    // then it goes to the next step, where the same procedure is repeated.
+    // All nodes which have previously been put where they belong does not need any work,
+    // and there is no need to go back, since no node will be swapped with an earlier location.
+    //
+    // Swaps never drop or duplicate nodes, but a generator that is not a permutation
+    // would loop forever or leave nodes in the wrong place, so check it during development.
+    debug_assert!(is_permutation(gen, nodes.len()), "expected a permutation of the nodes");
+    for i in 0..nodes.len() {
+        while gen[i] != i {
+            let j = gen[i];
+            nodes.swap(i, j);
+            gen.swap(i, j);
+            on_swap(i, j);
+        }
+    }
+}
+
+/// Searches the ordering constraints for a cycle.
+///
+/// Returns the nodes of the first cycle found, in the order of the arrows.
+fn find_cycle<T, I, C>(nodes: &mut [T], children: &C) -> Option<Vec<usize>>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let (offsets, targets) = constraints(nodes, children);
+
+    // Depth first search, where nodes on the stack are marked as visiting.
+    const UNVISITED: u8 = 0;
+    const VISITING: u8 = 1;
+    const VISITED: u8 = 2;
+    let mut state = vec![UNVISITED; n];
+    let mut stack: Vec<(usize, usize)> = vec![];
+    for start in 0..n {
+        if state[start] != UNVISITED {
+            continue;
+        }
+        state[start] = VISITING;
+        stack.push((start, offsets[start]));
+        while let Some(&(a, k)) = stack.last() {
+            if k == offsets[a + 1] {
+                state[a] = VISITED;
+                stack.pop();
+                continue;
+            }
+            let b = targets[k];
+            stack.last_mut().unwrap().1 += 1;
+            match state[b] {
+                UNVISITED => {
+                    state[b] = VISITING;
+                    stack.push((b, offsets[b]));
+                }
+                VISITING => {
+                    let pos = stack.iter().position(|&(c, _)| c == b).unwrap();
+                    return Some(stack[pos..].iter().map(|&(c, _)| c).collect());
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Collects the ordering constraints of a tree or DAG.
+///
+/// The constraints are arrows from every parent to its children,
+/// and from every child to its next sibling.
+/// Returns the arrows grouped by source node, where the targets of node `i`
+/// are stored at `targets[offsets[i]..offsets[i + 1]]`.
+fn constraints<T, I, C>(nodes: &mut [T], children: &C) -> (Vec<usize>, Vec<usize>)
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let mut edges: Vec<(usize, usize)> = vec![];
+    for (i, node) in nodes.iter_mut().enumerate() {
+        let children = children(node);
+        for j in 0..children.len() {
+            let a = children[j].to_usize();
+            if a != i {
+                edges.push((i, a));
+            }
+            if let Some(b) = children.get(j + 1) {
+                let b = b.to_usize();
+                if a != b {
+                    edges.push((a, b));
+                }
+            }
+        }
+    }
+    let mut offsets = vec![0; n + 1];
+    for &(a, _) in &edges {
+        offsets[a + 1] += 1;
+    }
+    for i in 0..n {
+        offsets[i + 1] += offsets[i];
+    }
+    let mut targets = vec![0; edges.len()];
+    let mut next = offsets.clone();
+    for &(a, b) in &edges {
+        targets[next[a]] = b;
+        next[a] += 1;
+    }
+    (offsets, targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_forest, Node};
+
+    #[derive(PartialEq, Debug)]
+    struct DagNode {
+        val: u32,
+        parents: Vec<usize>,
+        children: Vec<usize>,
+    }
+
+    impl TreeNode for Node {
+        type Index = usize;
+        fn parent(&mut self) -> &mut Option<usize> {&mut self.parent}
+        fn children(&mut self) -> &mut [usize] {&mut self.children}
+    }
+
+    impl super::DagNode for DagNode {
+        type Index = usize;
+        fn parents(&mut self) -> &mut [usize] {&mut self.parents}
+        fn children(&mut self) -> &mut [usize] {&mut self.children}
+    }
+
+    #[test]
+    fn empty() {
+        let mut nodes: Vec<Node> = vec![];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.len(), 0);
+    }
+
+    #[test]
+    fn one() {
+        let mut nodes: Vec<Node> = vec![Node {
+            val: 0,
+            parent: None,
+            children: vec![],
+        }];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![Node {
+                val: 0,
+                parent: None,
+                children: vec![]
+            }]
+        );
+    }
+
+    #[test]
+    fn two() {
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: None,
+                children: vec![],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![]
+                },
+                Node {
+                    val: 1,
+                    parent: None,
+                    children: vec![]
+                },
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 1,
+                parent: Some(1),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![0],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn three() {
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(1),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(2),
+                children: vec![0],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![1],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 1,
+                parent: Some(2),
+                children: vec![1],
+            },
+            Node {
+                val: 2,
+                parent: Some(0),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![0],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![1, 0],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 2]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 1,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![0, 1],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 2]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 1,
+                parent: Some(1),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![0, 2],
+            },
+            Node {
+                val: 2,
+                parent: Some(1),
+                children: vec![],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 2]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(1),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2, 0],
+            },
+            Node {
+                val: 1,
+                parent: Some(1),
+                children: vec![],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 2]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn four() {
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 3,
+                parent: Some(1),
+                children: vec![],
+            },
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![0],
+            },
+            Node {
+                val: 1,
+                parent: Some(3),
+                children: vec![1],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![3]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(2),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![1],
+            },
+            Node {
+                val: 3,
+                parent: Some(0),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(3),
+                children: vec![0],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![3]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(2),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(3),
+                children: vec![1],
+            },
+            Node {
+                val: 3,
+                parent: Some(0),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![3],
+            },
+            Node {
+                val: 1,
+                parent: Some(2),
+                children: vec![0],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![3]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(2),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 3,
+                parent: Some(3),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(3),
+                children: vec![0],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2, 1],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 3]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 3,
+                parent: Some(3),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: None,
+                children: vec![],
+            },
+            Node {
+                val: 2,
+                parent: Some(3),
+                children: vec![],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2, 0],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![2, 3]
+                },
+                Node {
+                    val: 1,
+                    parent: None,
+                    children: vec![]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(0),
+                    children: vec![]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(0),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 3,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 2,
+                parent: Some(2),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(3),
+                children: vec![1, 0],
+            },
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![2],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2, 3]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(1),
+                    children: vec![]
+                }
+            ]
+        );
+
+        let mut nodes: Vec<Node> = vec![
+            Node {
+                val: 0,
+                parent: None,
+                children: vec![3, 2],
+            },
+            Node {
+                val: 2,
+                parent: Some(3),
+                children: vec![],
+            },
+            Node {
+                val: 3,
+                parent: Some(0),
+                children: vec![],
+            },
+            Node {
+                val: 1,
+                parent: Some(0),
+                children: vec![1],
+            },
+        ];
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    val: 0,
+                    parent: None,
+                    children: vec![1, 3]
+                },
+                Node {
+                    val: 1,
+                    parent: Some(0),
+                    children: vec![2]
+                },
+                Node {
+                    val: 2,
+                    parent: Some(1),
+                    children: vec![]
+                },
+                Node {
+                    val: 3,
+                    parent: Some(0),
+                    children: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn try_sort_dag_shared() {
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![2, 3] },
+            DagNode { val: 3, parents: vec![2, 3], children: vec![] },
+            DagNode { val: 1, parents: vec![0], children: vec![1] },
+            DagNode { val: 2, parents: vec![0], children: vec![1] },
+        ];
+        assert_eq!(try_sort_dag(&mut nodes, |n| &mut n.parents, |n| &mut n.children), Ok(()));
+        assert_eq!(
+            nodes,
+            vec![
+                DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+                DagNode { val: 1, parents: vec![0], children: vec![3] },
+                DagNode { val: 2, parents: vec![0], children: vec![3] },
+                DagNode { val: 3, parents: vec![1, 2], children: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn try_sort_dag_cycle() {
+        // A
+        // |- B
+        //    |- D
+        //    |- C
+        // |- C
+        //    |- D
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+            DagNode { val: 1, parents: vec![0], children: vec![3, 2] },
+            DagNode { val: 2, parents: vec![0, 1], children: vec![3] },
+            DagNode { val: 3, parents: vec![1, 2], children: vec![] },
+        ];
+        let res = try_sort_dag(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        let node = res.unwrap_err().node;
+        assert!(node == 2 || node == 3);
+        // The nodes are left untouched.
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!(nodes[1].children, vec![3, 2]);
+    }
+
+    #[test]
+    fn sort_bounded_passes() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let res = sort_bounded(&mut nodes, |n| &mut n.parent, |n| &mut n.children, 10);
+        assert_eq!(res, Ok(2));
+        assert_eq!(nodes[0], Node { val: 0, parent: None, children: vec![1] });
+        let res = sort_bounded(&mut nodes, |n| &mut n.parent, |n| &mut n.children, 10);
+        assert_eq!(res, Ok(1));
+    }
+
+    #[test]
+    fn sort_bounded_shared_child() {
+        // `2` is a child of both `0` and `1`, but is ordered before `1` by `0`.
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![2, 1] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(1), children: vec![] },
+        ];
+        let res = sort_bounded(&mut nodes, |n| &mut n.parent, |n| &mut n.children, 100);
+        if let Err(SortError::NotConverged {passes, partial}) = res {
+            assert_eq!(passes, 100);
+            assert!(is_permutation(&partial, 3));
+        } else {
+            panic!("expected `NotConverged`, got {:?}", res);
+        }
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(nodes[0].children, vec![2, 1]);
+    }
+
+    #[test]
+    fn permutation() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let mut colors = vec!["blue", "green", "red"];
+        let perm = sort_permutation(&mut nodes, |n| &mut n.children);
+        assert_eq!(perm, vec![2, 1, 0]);
+        // The nodes are left untouched.
+        assert_eq!(nodes[2].children, vec![1, 0]);
+
+        apply_permutation(&mut colors, &perm);
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(colors, vec!["red", "green", "blue"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn apply_permutation_invalid() {
+        apply_permutation(&mut [0, 1], &[1, 1]);
     }
 
     #[test]
-    fn two() {
+    fn scratch() {
+        let mut scratch = vec![7, 7, 7, 7, 7];
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: None,
-                children: vec![],
-            },
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        sort_with_scratch(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &mut scratch);
+        assert_eq!(
+            nodes,
+            vec![
+                Node { val: 0, parent: None, children: vec![1] },
+                Node { val: 1, parent: Some(0), children: vec![] },
+            ]
+        );
+        assert_eq!(scratch, vec![0, 1]);
+    }
+
+    #[test]
+    fn u32_indices() {
+        #[derive(PartialEq, Debug)]
+        struct SmallNode {
+            val: u32,
+            parent: Option<u32>,
+            children: Vec<u32>,
+        }
+
+        let mut nodes: Vec<SmallNode> = vec![
+            SmallNode { val: 2, parent: Some(1), children: vec![] },
+            SmallNode { val: 1, parent: Some(2), children: vec![0] },
+            SmallNode { val: 0, parent: None, children: vec![1] },
         ];
         sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
         assert_eq!(
             nodes,
             vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![]
-                },
-                Node {
-                    val: 1,
-                    parent: None,
-                    children: vec![]
-                },
+                SmallNode { val: 0, parent: None, children: vec![1] },
+                SmallNode { val: 1, parent: Some(0), children: vec![2] },
+                SmallNode { val: 2, parent: Some(1), children: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn subtree() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![4, 1] },
+            Node { val: 4, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: Some(4), children: vec![] },
+            Node { val: 5, parent: None, children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![5, 2] },
+            Node { val: 2, parent: Some(4), children: vec![] },
+        ];
+        sort_subtree(&mut nodes, 4, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(
+            nodes,
+            vec![
+                Node { val: 0, parent: None, children: vec![2, 1] },
+                Node { val: 4, parent: Some(0), children: vec![] },
+                Node { val: 1, parent: Some(0), children: vec![4, 5] },
+                Node { val: 5, parent: None, children: vec![] },
+                Node { val: 2, parent: Some(2), children: vec![] },
+                Node { val: 3, parent: Some(2), children: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn counting() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0, 1] },
+        ];
+        let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(stats, SortStats {passes: 2, generator_swaps: 2, data_swaps: 2});
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+
+        let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(stats, SortStats {passes: 1, generator_swaps: 0, data_swaps: 0});
+    }
+
+    #[test]
+    fn checked_shared_child() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        let res = sort_checked(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(DuplicateParentError {node: 2, first_parent: 0, second_parent: 1}));
+
+        nodes[0].children = vec![1];
+        let res = sort_checked(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn inverse_permutation() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 2, parent: Some(3), children: vec![] },
+            Node { val: 1, parent: Some(3), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let perm = sort_permutation(&mut nodes, |n| &mut n.children);
+        let inv = sort_inverse_permutation(&mut nodes, |n| &mut n.children);
+        for i in 0..nodes.len() {
+            assert_eq!(inv[perm[i]], i);
+            assert_eq!(perm[inv[i]], i);
+        }
+
+        let vals: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(node.val, vals[inv[i]]);
+        }
+    }
+
+    #[test]
+    fn each_child() {
+        use alloc::collections::VecDeque;
+
+        #[derive(PartialEq, Debug)]
+        struct DequeNode {
+            val: u32,
+            parent: Option<usize>,
+            children: VecDeque<usize>,
+        }
+
+        let mut nodes: Vec<DequeNode> = vec![
+            DequeNode { val: 1, parent: Some(2), children: VecDeque::new() },
+            DequeNode { val: 2, parent: Some(2), children: VecDeque::new() },
+            DequeNode { val: 0, parent: None, children: vec![0, 1].into() },
+        ];
+        sort_each(&mut nodes, |n| &mut n.parent, |n, f| n.children.iter_mut().for_each(f));
+        assert_eq!(
+            nodes,
+            vec![
+                DequeNode { val: 0, parent: None, children: vec![1, 2].into() },
+                DequeNode { val: 1, parent: Some(0), children: VecDeque::new() },
+                DequeNode { val: 2, parent: Some(0), children: VecDeque::new() },
+            ]
+        );
+    }
+
+    #[test]
+    fn ordered() {
+        let new_nodes = || -> Vec<Node> {
+            vec![
+                Node { val: 1, parent: Some(3), children: vec![] },
+                Node { val: 3, parent: Some(3), children: vec![] },
+                Node { val: 2, parent: Some(3), children: vec![] },
+                Node { val: 0, parent: None, children: vec![0, 2, 1] },
+            ]
+        };
+
+        let mut nodes = new_nodes();
+        sort_ordered(&mut nodes, |n| &mut n.parent, |n| &mut n.children, SortOrder::Ascending);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!(nodes[0].children, vec![1, 2, 3]);
+
+        let mut nodes = new_nodes();
+        sort_ordered(&mut nodes, |n| &mut n.parent, |n| &mut n.children, SortOrder::Descending);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 3, 2, 1]);
+        assert_eq!(nodes[0].children, vec![3, 2, 1]);
+        for node in &nodes[1..] {
+            assert_eq!(node.parent, Some(0));
+        }
+    }
+
+    #[test]
+    fn tree_node_trait() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        sort_nodes(&mut nodes);
+        assert_eq!(
+            nodes,
+            vec![
+                Node { val: 0, parent: None, children: vec![1] },
+                Node { val: 1, parent: Some(0), children: vec![] },
             ]
         );
+    }
+
+    #[test]
+    fn dag_node_trait() {
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 2, parents: vec![1, 2], children: vec![] },
+            DagNode { val: 1, parents: vec![2], children: vec![0] },
+            DagNode { val: 0, parents: vec![], children: vec![1, 0] },
+        ];
+        sort_dag_nodes(&mut nodes);
+        assert_eq!(
+            nodes,
+            vec![
+                DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+                DagNode { val: 1, parents: vec![0], children: vec![2] },
+                DagNode { val: 2, parents: vec![1, 0], children: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn sorted() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        assert!(is_sorted(&mut nodes, |n| &mut n.children));
+
+        nodes[0].children = vec![2, 1];
+        assert!(!is_sorted(&mut nodes, |n| &mut n.children));
+
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        assert!(!is_sorted(&mut nodes, |n| &mut n.children));
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert!(is_sorted(&mut nodes, |n| &mut n.children));
+    }
+
+    #[test]
+    fn already_sorted() {
+        let new_nodes = || -> Vec<Node> {
+            vec![
+                Node { val: 0, parent: None, children: vec![1, 3] },
+                Node { val: 1, parent: Some(0), children: vec![2] },
+                Node { val: 2, parent: Some(1), children: vec![] },
+                Node { val: 3, parent: Some(0), children: vec![] },
+            ]
+        };
+        let mut nodes = new_nodes();
+        let res = sort_bounded(&mut nodes, |n| &mut n.parent, |n| &mut n.children, 1);
+        assert_eq!(res, Ok(1));
+        assert_eq!(nodes, new_nodes());
+
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, new_nodes());
+    }
+
+    #[test]
+    fn permute_in_place() {
+        let mut nodes = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let mut colors = vec!["blue", "red"];
+        let mut perm = sort_permutation(&mut nodes, |n| &mut n.children);
+        super::permute_in_place(&mut colors, &mut perm);
+        assert_eq!(colors, vec!["red", "blue"]);
+        assert_eq!(perm, vec![0, 1]);
+    }
+
+    #[test]
+    fn depths() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 3, parent: Some(2), children: vec![] },
+            Node { val: 2, parent: None, children: vec![4] },
+            Node { val: 1, parent: Some(3), children: vec![0] },
+            Node { val: 0, parent: None, children: vec![2] },
+            Node { val: 4, parent: Some(1), children: vec![] },
+        ];
+        let depths = sort_with_depths(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 2, 1, 3, 4]);
+        assert_eq!(depths, vec![0, 0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn forest() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: None, children: vec![3] },
+            Node { val: 3, parent: Some(2), children: vec![] },
+            Node { val: 4, parent: None, children: vec![] },
+        ];
+        sort_forest(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &[4, 2, 0]);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![4, 2, 0, 3, 1]);
+        assert_eq!(nodes[1].children, vec![3]);
+        assert_eq!(nodes[2].children, vec![4]);
+        assert_eq!(nodes[4].parent, Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "root `1` has a parent")]
+    fn forest_root_with_parent() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        sort_forest(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &[1, 0]);
+    }
+
+    /// The FNV-1a hash function, to test hashing without `std`.
+    struct Fnv(u64);
+
+    impl Hasher for Fnv {
+        fn finish(&self) -> u64 {self.0}
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    fn hash(nodes: &mut [Node]) -> u64 {
+        let mut hasher = Fnv(0xcbf29ce484222325);
+        structural_hash(nodes, |n| &mut n.children, |n| n.val as u64, &mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn structural_hash_layout() {
+        let mut a: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        let mut b: Vec<Node> = vec![
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0, 1] },
+        ];
+        assert_eq!(hash(&mut a), hash(&mut b));
+        // Hashing does not sort the nodes.
+        assert_eq!(b[2].children, vec![0, 1]);
+
+        b[1].val = 3;
+        assert_ne!(hash(&mut a), hash(&mut b));
+        b[1].val = 2;
+        b[2].children = vec![1, 0];
+        assert_ne!(hash(&mut a), hash(&mut b));
+    }
+
+    #[test]
+    fn tree_eq_layout() {
+        let mut a: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: None, children: vec![] },
+        ];
+        let mut b: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+            Node { val: 2, parent: None, children: vec![] },
+        ];
+        assert!(tree_eq(&mut a, &mut b, |n| &mut n.children, |n| n.val));
+        // Comparing does not sort the nodes.
+        assert_eq!(b[1].children, vec![0]);
+
+        b[2].val = 3;
+        assert!(!tree_eq(&mut a, &mut b, |n| &mut n.children, |n| n.val));
+        b[2].val = 2;
+        b[1].children = vec![];
+        assert!(!tree_eq(&mut a, &mut b, |n| &mut n.children, |n| n.val));
+        assert!(!tree_eq(&mut a, &mut b[..2], |n| &mut n.children, |n| n.val));
+    }
+
+    #[test]
+    fn record() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 2, parent: Some(3), children: vec![] },
+            Node { val: 3, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(3), children: vec![1] },
+            Node { val: 0, parent: None, children: vec![2, 0] },
+        ];
+        let original: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+        let log = sort_record(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        let sorted: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+        assert_eq!(sorted, vec![0, 1, 3, 2]);
+
+        let mut data = original.clone();
+        for &(i, j) in &log {
+            data.swap(i, j);
+        }
+        assert_eq!(data, sorted);
+        for &(i, j) in log.iter().rev() {
+            data.swap(i, j);
+        }
+        assert_eq!(data, original);
+
+        assert_eq!(sort_record(&mut nodes, |n| &mut n.parent, |n| &mut n.children), vec![]);
+    }
+
+    #[test]
+    fn sentinel() {
+        struct FfiNode {
+            val: u32,
+            parent: usize,
+            children: Vec<usize>,
+        }
+
+        let mut nodes = vec![
+            FfiNode { val: 1, parent: 1, children: vec![] },
+            FfiNode { val: 0, parent: usize::MAX, children: vec![0] },
+        ];
+        sort_sentinel(&mut nodes, |n| &mut n.parent, |n| &mut n.children, usize::MAX);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1]);
+        assert_eq!(nodes[0].parent, usize::MAX);
+        assert_eq!(nodes[0].children, vec![1]);
+        assert_eq!(nodes[1].parent, 0);
+    }
+
+    #[test]
+    fn validated() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0, 2] },
+        ];
+        let res = sort_validated(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(BadIndex {node: 1, index: 2}));
+        assert_eq!(nodes[0].val, 1);
+
+        nodes[1].children = vec![0];
+        nodes[0].parent = Some(5);
+        let res = sort_validated(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(BadIndex {node: 0, index: 5}));
+
+        nodes[0].parent = Some(1);
+        let res = sort_validated(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn append() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        let sub: Vec<Node> = vec![
+            Node { val: 3, parent: Some(1), children: vec![] },
+            Node { val: 2, parent: None, children: vec![0] },
+        ];
+        append_subtree(&mut nodes, sub, 0, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, vec![
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![3] },
+            Node { val: 3, parent: Some(2), children: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn forest_roots() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: Some(3), children: vec![] },
+            Node { val: 1, parent: None, children: vec![2] },
+            Node { val: 2, parent: Some(1), children: vec![] },
+            Node { val: 3, parent: Some(4), children: vec![0] },
+            Node { val: 4, parent: None, children: vec![3] },
+        ];
+        assert_eq!(roots(&mut nodes, |n| &mut n.parent), vec![1, 4]);
+        assert_eq!(forest_partition(&mut nodes, |n| &mut n.parent), vec![4, 1, 1, 4, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "parent links contain a cycle")]
+    fn forest_partition_cycle() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: Some(1), children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![0] },
+        ];
+        forest_partition(&mut nodes, |n| &mut n.parent);
+    }
+
+    #[test]
+    fn dag_dedup() {
+        // Listing `2` twice as a child of the root would require it to be before and after `3`.
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![2, 3, 2] },
+            DagNode { val: 3, parents: vec![2, 3, 3], children: vec![] },
+            DagNode { val: 1, parents: vec![0, 0], children: vec![1, 1] },
+            DagNode { val: 2, parents: vec![0], children: vec![1] },
+        ];
+        sort_dag_dedup(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        assert_eq!(nodes, vec![
+            DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+            DagNode { val: 1, parents: vec![0], children: vec![3] },
+            DagNode { val: 2, parents: vec![0], children: vec![3] },
+            DagNode { val: 3, parents: vec![1, 2], children: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn consistency() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        let res = check_consistency(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(Inconsistency::MissingChild {parent: 0, child: 2}));
+
+        nodes[1].children = vec![2];
+        let res = check_consistency(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(Inconsistency::WrongParent {parent: 1, child: 2}));
+
+        nodes[1].children = vec![];
+        nodes[0].children = vec![1, 2];
+        let res = check_consistency(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn movement() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let stats = sort_movement(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 3, 1]);
+        assert_eq!(stats, MovementStats {total_displacement: 4, max_displacement: 2, unmoved: 1});
+
+        let stats = sort_movement(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(stats, MovementStats {total_displacement: 0, max_displacement: 0, unmoved: 3});
+    }
+
+    #[test]
+    fn try_sort_errors() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1, 3] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        let res = try_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(SortError::OutOfBounds(BadIndex {node: 0, index: 3})));
+
+        nodes.push(Node { val: 2, parent: Some(1), children: vec![1] });
+        nodes[0].children = vec![1];
+        nodes[1].children = vec![2];
+        let res = try_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(SortError::DuplicateParent(DuplicateParentError {
+            node: 1,
+            first_parent: 0,
+            second_parent: 2,
+        })));
+
+        // A cycle without shared nodes.
+        nodes[0].children = vec![];
+        let res = try_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert!(matches!(res, Err(SortError::NotConverged {..})));
+
+        nodes[0].children = vec![1];
+        nodes[2].children = vec![];
+        let res = try_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn edges() {
+        let mut vals = vec![1, 0, 2];
+        let mut edges = vec![(1, 0), (0, 2)];
+        let perm = sort_edges(3, &mut edges);
+        assert_eq!(perm, vec![1, 0, 2]);
+        assert_eq!(edges, vec![(0, 1), (1, 2)]);
+        apply_permutation(&mut vals, &perm);
+        assert_eq!(vals, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn bracket_string() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 6, parent: Some(3), children: vec![4, 2] },
+            Node { val: 7, parent: None, children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 12, parent: None, children: vec![5, 0] },
+            Node { val: 3, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(3), children: vec![] },
+        ];
+        let s = to_bracket_string(&mut nodes, |n| &mut n.parent, |n| &mut n.children,
+            |n| alloc::format!("{}", n.val));
+        assert_eq!(s, "12(2, 6(3, 2)), 7");
+    }
+
+    #[test]
+    fn sort_unsort() {
+        let original: Vec<Node> = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let mut nodes = original.clone();
+        let gen = sort_permutation(&mut nodes, |n| &mut n.children);
+        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+
+        rewrite_indices(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &invert(&gen));
+        unsort(&mut nodes, &gen);
+        assert_eq!(nodes, original);
+    }
+
+    #[test]
+    fn idempotent() {
+        let mut seed = 1;
+        for n in 0..200 {
+            let mut nodes = random_forest(n % 50, &mut seed);
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            let once = nodes.clone();
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(nodes, once);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sibling `1` is stored before its previous sibling `2`")]
+    fn sorted_invariants_siblings() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![2, 1] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+    }
+
+    #[test]
+    fn leaves() {
+        let mut nodes: Vec<Node> = (0..100).rev()
+            .map(|val| Node { val, parent: None, children: vec![] })
+            .collect();
+        let expected = nodes.clone();
+        let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(stats, SortStats {passes: 1, generator_swaps: 0, data_swaps: 0});
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn split() {
+        struct Handle {
+            parent: Option<usize>,
+            children: Vec<usize>,
+        }
+
+        let mut structure = vec![
+            Handle { parent: Some(2), children: vec![] },
+            Handle { parent: Some(2), children: vec![] },
+            Handle { parent: None, children: vec![1, 0] },
+        ];
+        let mut data = vec!["c", "b", "a"];
+        sort_split(&mut structure, &mut data, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(data, vec!["a", "b", "c"]);
+        assert_eq!(structure[0].children, vec![1, 2]);
+        assert_eq!(structure[2].parent, Some(0));
+    }
 
+    #[test]
+    fn dag_levels() {
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![2, 3] },
+            DagNode { val: 3, parents: vec![2, 3], children: vec![] },
+            DagNode { val: 1, parents: vec![0], children: vec![1] },
+            DagNode { val: 2, parents: vec![0], children: vec![1] },
+            DagNode { val: 4, parents: vec![], children: vec![] },
+        ];
+        let levels = sort_dag_levels(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(levels, vec![0, 1, 1, 2, 0]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "node `2` is a child of both `0` and `1`")]
+    fn shared_child_debug() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 1,
-                parent: Some(1),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![0],
-            },
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(0), children: vec![] },
         ];
         sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![]
-                },
-            ]
-        );
     }
 
     #[test]
-    fn three() {
+    fn extra_constraints() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(1),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(2),
-                children: vec![0],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![1],
-            },
+            Node { val: 0, parent: None, children: vec![2] },
+            Node { val: 2, parent: None, children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![]
-                }
-            ]
-        );
+        let res = sort_with_constraints(&mut nodes, |n| &mut n.parent, |n| &mut n.children,
+            &[(2, 1)]);
+        assert_eq!(res, Ok(()));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(nodes[0].children, vec![1]);
+
+        // The child can not be stored before its parent.
+        let res = sort_with_constraints(&mut nodes, |n| &mut n.parent, |n| &mut n.children,
+            &[(1, 0)]);
+        assert!(matches!(res, Err(SortError::NotConverged {..})));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+
+        let res = sort_with_constraints(&mut nodes, |n| &mut n.parent, |n| &mut n.children,
+            &[(2, 1), (1, 3)]);
+        assert_eq!(res, Err(SortError::OutOfBounds(BadIndex {node: 1, index: 3})));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn canonical_bytes() {
+        use std::io::Write;
 
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![2, 3] },
+            DagNode { val: 3, parents: vec![2, 3], children: vec![] },
+            DagNode { val: 1, parents: vec![0], children: vec![1] },
+            DagNode { val: 2, parents: vec![0], children: vec![1] },
+        ];
+        let mut bytes: Vec<u8> = vec![];
+        write_canonical(&mut nodes, |n| &mut n.parents, |n| &mut n.children, &mut bytes,
+            |n, w| {
+                w.write_all(&[n.val as u8, n.children.len() as u8])?;
+                w.write_all(&n.children.iter().map(|&ch| ch as u8).collect::<Vec<u8>>())
+            }).unwrap();
+        assert_eq!(bytes, vec![0, 2, 1, 2, 1, 1, 3, 2, 1, 3, 3, 0]);
+    }
+
+    #[test]
+    fn one_tree() {
+        // The trees of `0` and `10` interleave.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 1,
-                parent: Some(2),
-                children: vec![1],
-            },
-            Node {
-                val: 2,
-                parent: Some(0),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![0],
-            },
+            Node { val: 11, parent: Some(3), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 10, parent: None, children: vec![0] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![]
+        sort_one(&mut nodes, |n| &mut n.parent, |n| &mut n.children, 3);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![10, 1, 0, 11]);
+        assert_eq!(nodes[0].children, vec![3]);
+        assert_eq!(nodes[3].parent, Some(0));
+        assert_eq!(nodes[2].children, vec![1]);
+    }
+
+    #[test]
+    fn with_passes() {
+        let mut nodes = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        assert_eq!(sort_with_passes(&mut nodes, |n| &mut n.parent, |n| &mut n.children), 2);
+        assert_eq!(nodes[0].val, 0);
+        assert_eq!(sort_with_passes(&mut nodes, |n| &mut n.parent, |n| &mut n.children), 1);
+    }
+
+    #[test]
+    fn cmp_siblings() {
+        let mut nodes = vec![
+            Node { val: 0, parent: None, children: vec![1, 2, 3] },
+            Node { val: 3, parent: Some(0), children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        sort_cmp(&mut nodes, |n| &mut n.parent, |n| &mut n.children, |a, b| a.val.cmp(&b.val));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!(nodes[0].children, vec![1, 2, 3]);
+        assert_eq!(nodes[3].parent, Some(0));
+    }
+
+    #[test]
+    fn adjacency() {
+        // The edge `(1, 2)` requires `2` before `3`, contradicting the edge order of `0`.
+        let edges = [(0, 3), (0, 1), (0, 2), (1, 2), (2, 3), (1, 2)];
+        let (parents, children) = from_adjacency(4, &edges).unwrap();
+        assert_eq!(parents, vec![vec![], vec![0], vec![0, 1], vec![0, 2]]);
+        assert_eq!(children, vec![vec![1, 2, 3], vec![2], vec![3], vec![]]);
+
+        let mut nodes: Vec<DagNode> = parents.into_iter().zip(children).enumerate()
+            .map(|(i, (parents, children))| DagNode { val: i as u32, parents, children })
+            .collect();
+        nodes.swap(0, 3);
+        for node in &mut nodes {
+            for j in node.parents.iter_mut().chain(node.children.iter_mut()) {
+                if *j == 0 || *j == 3 {
+                    *j = 3 - *j;
                 }
-            ]
-        );
+            }
+        }
+        assert_eq!(try_sort_dag(&mut nodes, |n| &mut n.parents, |n| &mut n.children), Ok(()));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+
+        let res = from_adjacency(4, &[(0, 1), (1, 2), (2, 3), (3, 1)]);
+        let node = res.unwrap_err().node;
+        assert!(node >= 1);
+        assert_eq!(from_adjacency(1, &[(0, 0)]), Err(NotADag {node: 0}));
+    }
+
+    #[test]
+    fn dag_vec() {
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 1, parents: vec![2, 2, 9], children: vec![1] },
+            DagNode { val: 2, parents: vec![], children: vec![] },
+            DagNode { val: 0, parents: vec![], children: vec![0, 0, 7] },
+        ];
+        sort_dag_vec(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        assert_eq!(nodes, vec![
+            DagNode { val: 0, parents: vec![], children: vec![2] },
+            DagNode { val: 2, parents: vec![], children: vec![] },
+            DagNode { val: 1, parents: vec![0], children: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn with_map() {
+        let mut nodes = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let old = nodes.clone();
+        let map = sort_with_map(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        for (i, node) in old.iter().enumerate() {
+            assert_eq!(nodes[map[i]].val, node.val);
+        }
+        assert_eq!(sort_with_map(&mut nodes, |n| &mut n.parent, |n| &mut n.children),
+            vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn cycles_same_as_retrace() {
+        let mut seed = 11;
+        for n in 0..40 {
+            let mut expected = random_forest(n, &mut seed);
+            let mut nodes = expected.clone();
+            sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+            sort_large(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(nodes, expected);
+        }
+
+        // Nodes that own memory are moved without being duplicated or dropped.
+        let mut data: Vec<Vec<u32>> = (0..6).map(|i| vec![i]).collect();
+        let mut gen = vec![3, 0, 1, 2, 5, 4];
+        retrace_cycles(&mut data, &mut gen);
+        assert_eq!(data, vec![vec![1], vec![2], vec![3], vec![0], vec![5], vec![4]]);
+        assert_eq!(gen, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn preserves_values() {
+        let mut seed = 17;
+        for n in 0..60 {
+            // `random_forest` tags every node with a unique value.
+            let mut nodes = random_forest(n, &mut seed);
+            let mut expected: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            let mut vals: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+            expected.sort_unstable();
+            vals.sort_unstable();
+            assert_eq!(vals, expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a permutation of the nodes")]
+    #[cfg(debug_assertions)]
+    fn retrace_not_permutation() {
+        retrace(&mut [0, 1, 2], &mut [1, 1, 2]);
+    }
+
+    #[test]
+    fn one_based() {
+        struct FfiNode {
+            val: u32,
+            parent: u32,
+            children: Vec<u32>,
+        }
+
+        // The primes example, where `0` is null and every index is one more.
+        let mut nodes = vec![
+            FfiNode { val: 2, parent: 2, children: vec![] },
+            FfiNode { val: 6, parent: 4, children: vec![5, 1] },
+            FfiNode { val: 2, parent: 4, children: vec![] },
+            FfiNode { val: 12, parent: 0, children: vec![3, 2] },
+            FfiNode { val: 3, parent: 2, children: vec![] },
+        ];
+        sort_one_based(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![12, 2, 6, 3, 2]);
+        assert_eq!(nodes.iter().map(|n| n.parent).collect::<Vec<u32>>(), vec![0, 1, 1, 3, 3]);
+        assert_eq!(nodes[0].children, vec![2, 3]);
+        assert_eq!(nodes[2].children, vec![4, 5]);
+    }
 
+    #[test]
+    fn parents_only() {
+        // Siblings in the wrong order are left alone.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![1, 0],
-            },
+            Node { val: 0, parent: None, children: vec![2, 1] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 2]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(0),
-                    children: vec![]
+        let expected = nodes.clone();
+        sort_parents_only(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, expected);
+
+        let mut seed = 23;
+        for n in 0..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            sort_parents_only(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            for (i, node) in nodes.iter().enumerate() {
+                if let Some(p) = node.parent {
+                    assert!(p < i);
                 }
-            ]
-        );
+                assert!(node.children.iter().all(|&ch| nodes[ch].parent == Some(i)));
+            }
+        }
+    }
+
+    #[test]
+    fn dag_consistent() {
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+            DagNode { val: 1, parents: vec![0], children: vec![3, 2] },
+            DagNode { val: 2, parents: vec![0, 1], children: vec![3] },
+            DagNode { val: 3, parents: vec![1, 2], children: vec![] },
+        ];
+        assert!(!dag_is_consistent(&mut nodes, |n| &mut n.children));
+        nodes[1].children = vec![2, 3];
+        assert!(dag_is_consistent(&mut nodes, |n| &mut n.children));
+        sort_dag(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+    }
+
+    #[test]
+    fn resume() {
+        let nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+
+        // The generator puts the nodes in order, so no swaps are needed.
+        let mut sorted = nodes.clone();
+        let mut gen = vec![1, 0];
+        sort_resume(&mut sorted, |n| &mut n.parent, |n| &mut n.children, &mut gen);
+        assert_eq!(sorted[0], Node { val: 0, parent: None, children: vec![1] });
+        assert_eq!(gen, vec![0, 1]);
+
+        // A generator of the wrong length is reset.
+        let mut sorted = nodes.clone();
+        let mut gen = vec![];
+        sort_resume(&mut sorted, |n| &mut n.parent, |n| &mut n.children, &mut gen);
+        assert_eq!(sorted[0].val, 0);
+
+        let mut seed = 29;
+        for n in 0..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            let mut gen: Vec<usize> = (0..n).rev().collect();
+            sort_resume(&mut nodes, |n| &mut n.parent, |n| &mut n.children, &mut gen);
+            assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        }
+    }
 
+    #[test]
+    fn with_ranges() {
+        // a
+        // |- b
+        //    |- d
+        //       |- e
+        // |- c
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 1,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![0, 1],
-            },
+            Node { val: 0, parent: None, children: vec![3, 1] },
+            Node { val: 4, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: Some(4), children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![4] },
+            Node { val: 2, parent: Some(3), children: vec![2] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 2]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(0),
-                    children: vec![]
+        let ranges = sort_with_ranges(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ranges, vec![0..5, 1..4, 2..4, 3..4, 4..5]);
+        assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+
+        let mut seed = 31;
+        for n in 0..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            let ranges = sort_with_ranges(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            for j in 0..n {
+                let mut ancestor = Some(j);
+                while let Some(i) = ancestor {
+                    assert!(ranges[i].contains(&j));
+                    ancestor = nodes[i].parent;
                 }
-            ]
-        );
+            }
+            for (i, range) in ranges.iter().enumerate() {
+                for j in range.clone() {
+                    let mut a = j;
+                    while a != i {
+                        a = nodes[a].parent.unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dot() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let s = to_dot(&mut nodes, |n| &mut n.children, |_, n| alloc::format!("\"{}\"", n.val));
+        assert_eq!(s, concat!(
+            "digraph {\n",
+            "    0 [label=\"0: \\\"1\\\"\"];\n",
+            "    1 [label=\"1: \\\"0\\\"\"];\n",
+            "    1 -> 0;\n",
+            "}\n",
+        ));
+    }
+
+    #[test]
+    fn stable_roots() {
+        // Sorting moves the root `1` before the root `2`, unless roots are stable.
+        let nodes: Vec<Node> = vec![
+            Node { val: 3, parent: Some(2), children: vec![] },
+            Node { val: 2, parent: None, children: vec![] },
+            Node { val: 1, parent: None, children: vec![0] },
+        ];
+        let mut sorted = nodes.clone();
+        sort(&mut sorted, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(sorted.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![1, 2, 3]);
+
+        let mut sorted = nodes.clone();
+        let res = sort_stable_roots(&mut sorted, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+        assert_eq!(sorted.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![2, 1, 3]);
+        assert_eq!(sorted[1].children, vec![2]);
 
+        // A root that is listed as a child contradicts the order of roots.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 1,
-                parent: Some(1),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![0, 2],
-            },
-            Node {
-                val: 2,
-                parent: Some(1),
-                children: vec![],
-            },
+            Node { val: 0, parent: None, children: vec![] },
+            Node { val: 1, parent: None, children: vec![0] },
         ];
+        let res = sort_stable_roots(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert!(matches!(res, Err(SortError::NotConverged {..})));
+        assert_eq!(nodes[0].val, 0);
+    }
+
+    #[test]
+    fn swap_distance_same_as_retrace() {
+        let mut seed = 37;
+        for n in 0..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            let distance = swap_distance(&mut nodes, |n| &mut n.children);
+            let stats = sort_counting(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(distance, stats.data_swaps);
+        }
+    }
+
+    #[test]
+    fn golden_output() {
+        // Changing the result for the same input breaks users relying on determinism.
+        let mut seed = 41;
+        let mut nodes = random_forest(500, &mut seed);
         sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 2]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(0),
-                    children: vec![]
-                }
-            ]
-        );
+        let mut hasher = Fnv(0xcbf29ce484222325);
+        for node in &nodes {
+            // Fixed width and byte order, such that the hash is the same on every platform.
+            hasher.write(&(node.val as u64).to_le_bytes());
+            hasher.write(&node.parent.map_or(u64::MAX, |p| p as u64).to_le_bytes());
+        }
+        assert_eq!(hasher.finish(), 8852572016133724027);
+    }
+
+    #[test]
+    fn structural_hash_golden() {
+        // The hash is used for content-addressing, so it must not change between platforms.
+        let mut seed = 41;
+        let mut nodes = random_forest(500, &mut seed);
+        assert_eq!(hash(&mut nodes), 16552541049188983387);
+    }
+
+    #[test]
+    fn dag_checked_cycle() {
+        // A
+        // |- B
+        //    |- D
+        //    |- C
+        // |- C
+        //    |- D
+        let mut nodes: Vec<DagNode> = vec![
+            DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+            DagNode { val: 1, parents: vec![0], children: vec![3, 2] },
+            DagNode { val: 2, parents: vec![0, 1], children: vec![3] },
+            DagNode { val: 3, parents: vec![1, 2], children: vec![] },
+        ];
+        let res = sort_dag_checked(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        // `B` orders `D` before `C`, while `D` is a child of `C`.
+        assert_eq!(res, Err(vec![2, 3, 2]));
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+
+        nodes[1].children = vec![2, 3];
+        let res = sort_dag_checked(&mut nodes, |n| &mut n.parents, |n| &mut n.children);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn observed() {
+        let nodes: Vec<Node> = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let mut expected = nodes.clone();
+        sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+
+        let mut sorted = nodes.clone();
+        let mut log = vec![];
+        sort_observed(&mut sorted, |n| &mut n.parent, |n| &mut n.children,
+            |phase, i, j| log.push((phase, i, j)));
+        assert_eq!(sorted, expected);
+        assert_eq!(log, vec![
+            (Phase::Solve, 2, 1),
+            (Phase::Solve, 1, 0),
+            (Phase::Solve, 2, 1),
+            (Phase::Retrace, 0, 2),
+        ]);
+    }
 
+    #[test]
+    fn validate() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(1),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2, 0],
-            },
-            Node {
-                val: 1,
-                parent: Some(1),
-                children: vec![],
-            },
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(4), children: vec![] },
+            Node { val: 3, parent: Some(2), children: vec![] },
+            Node { val: 4, parent: Some(3), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 2]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(0),
-                    children: vec![]
-                }
-            ]
-        );
+        let res = validate_tree(&mut nodes, |n| &mut n.parent);
+        assert_eq!(res, Err(ParentCycle {nodes: vec![2, 4, 3]}));
+
+        nodes[3].parent = Some(1);
+        assert_eq!(validate_tree(&mut nodes, |n| &mut n.parent), Ok(()));
+
+        let mut nodes: Vec<Node> = vec![Node { val: 0, parent: Some(0), children: vec![] }];
+        let res = validate_tree(&mut nodes, |n| &mut n.parent);
+        assert_eq!(res, Err(ParentCycle {nodes: vec![0]}));
     }
 
     #[test]
-    fn four() {
+    fn renumber() {
+        let mut nodes = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+        let renumber = sort_renumber(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        let other = renumber.clone();
+        assert_eq!((renumber.get(0), renumber[1], renumber.len()), (2, 1, 3));
+        let f = other.as_fn();
+        assert_eq!(f(2), 0);
+        assert_eq!(nodes[f(0)].val, 2);
+    }
+
+    #[test]
+    fn live() {
+        // Dead nodes have `val` above 100 and meaningless links.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 3,
-                parent: Some(1),
-                children: vec![],
-            },
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![0],
-            },
-            Node {
-                val: 1,
-                parent: Some(3),
-                children: vec![1],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2],
-            },
+            Node { val: 101, parent: Some(7), children: vec![9] },
+            Node { val: 2, parent: Some(3), children: vec![] },
+            Node { val: 102, parent: None, children: vec![] },
+            Node { val: 0, parent: None, children: vec![4, 1] },
+            Node { val: 1, parent: Some(3), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![3]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(2),
-                    children: vec![]
-                }
-            ]
-        );
+        let res = sort_live(&mut nodes, |n| &mut n.parent, |n| &mut n.children, |n| n.val < 100);
+        assert_eq!(res, CompactResult {live_len: 3, freed: vec![3, 4]});
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 2, 101, 102]);
+        assert_eq!(nodes[0].children, vec![1, 2]);
+        assert_eq!(nodes[2].parent, Some(0));
+        assert_eq!(nodes[3].children, vec![9]);
+        assert_sorted_invariants(&mut nodes[..3], |n| &mut n.parent, |n| &mut n.children);
+    }
 
+    #[test]
+    fn with_sizes() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![1],
-            },
-            Node {
-                val: 3,
-                parent: Some(0),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(3),
-                children: vec![0],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2],
-            },
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
+            Node { val: 0, parent: None, children: vec![4, 0] },
+            Node { val: 4, parent: Some(4), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![3] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![3]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(2),
-                    children: vec![]
-                }
-            ]
-        );
+        let sizes = sort_with_sizes(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        let vals: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+        let size_of = |v: u32| sizes[vals.iter().position(|&w| w == v).unwrap()];
+        assert_eq!((size_of(0), size_of(1), size_of(2), size_of(3), size_of(4)), (4, 2, 1, 1, 1));
+    }
 
+    #[test]
+    fn normalized_children() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(3),
-                children: vec![1],
-            },
-            Node {
-                val: 3,
-                parent: Some(0),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![3],
-            },
-            Node {
-                val: 1,
-                parent: Some(2),
-                children: vec![0],
-            },
+            Node { val: 0, parent: None, children: vec![3, 1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: Some(0), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![3]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(2),
-                    children: vec![]
+        normalize_children(&mut nodes, |n| &mut n.children);
+        assert_eq!(nodes[0].children, vec![1, 2, 3]);
+        assert!(is_sorted(&mut nodes, |n| &mut n.children));
+    }
+
+    #[test]
+    fn dyn_nodes() {
+        struct Leaf {
+            parent: Option<usize>,
+        }
+
+        impl TreeNode for Leaf {
+            type Index = usize;
+            fn parent(&mut self) -> &mut Option<usize> {&mut self.parent}
+            fn children(&mut self) -> &mut [usize] {&mut []}
+        }
+
+        let mut nodes: Vec<Box<dyn TreeNode<Index = usize>>> = vec![
+            Box::new(Leaf { parent: Some(1) }),
+            Box::new(Node { val: 0, parent: None, children: vec![0] }),
+        ];
+        sort_dyn(&mut nodes);
+        assert_eq!(*nodes[0].parent(), None);
+        assert_eq!(nodes[0].children(), &[1]);
+        assert_eq!(*nodes[1].parent(), Some(0));
+    }
+
+    #[test]
+    fn self_child() {
+        let nodes: Vec<Node> = vec![
+            Node { val: 1, parent: Some(1), children: vec![0] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let mut sorted = nodes.clone();
+        assert_eq!(find_self_child(&mut sorted, |n| &mut n.children), Some(0));
+        let res = try_sort(&mut sorted, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(res, Err(SortError::SelfChild(0)));
+        assert_eq!(sorted, nodes);
+
+        sort_drop_self_children(&mut sorted, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(sorted, vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ]);
+        assert_eq!(find_self_child(&mut sorted, |n| &mut n.children), None);
+    }
+
+    #[test]
+    fn try_edges() {
+        let mut edges = vec![(1, 0), (1, 3)];
+        assert_eq!(try_sort_edges(2, &mut edges),
+            Err(EdgeError::OutOfBounds {edge: 1, endpoint: 3}));
+        assert_eq!(edges, vec![(1, 0), (1, 3)]);
+
+        edges.pop();
+        assert_eq!(try_sort_edges(2, &mut edges), Ok(vec![1, 0]));
+        assert_eq!(edges, vec![(0, 1)]);
+
+        let mut edges = vec![(0, 1), (1, 2), (2, 0)];
+        assert_eq!(try_sort_edges(3, &mut edges), Err(EdgeError::Cycle));
+        assert_eq!(edges, vec![(0, 1), (1, 2), (2, 0)]);
+        assert_eq!(try_sort_edges(2, &mut [(0, 1), (1, 0)]), Err(EdgeError::Cycle));
+
+        let mut edges = vec![(0, 1), (0, 2), (2, 3)];
+        assert_eq!(try_sort_edges(4, &mut edges), Ok(vec![0, 1, 2, 3]));
+        assert_eq!(edges, vec![(0, 1), (0, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn bfs() {
+        // A balanced binary tree where node `k` has the children `2k + 1` and `2k + 2`,
+        // stored in a scrambled order.
+        let n = 15;
+        let pos: Vec<usize> = (0..n).map(|k| (k * 7 + 3) % n).collect();
+        let mut nodes: Vec<Node> = (0..n).map(|_| Node {
+            val: 0,
+            parent: None,
+            children: vec![],
+        }).collect();
+        for k in 0..n {
+            nodes[pos[k]].val = k as u32;
+            if k > 0 {
+                nodes[pos[k]].parent = Some(pos[(k - 1) / 2]);
+                nodes[pos[(k - 1) / 2]].children.push(pos[k]);
+            }
+        }
+        sort_bfs(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(),
+            (0..n as u32).collect::<Vec<u32>>());
+        for (k, node) in nodes.iter().enumerate() {
+            assert_eq!(node.parent, if k == 0 {None} else {Some((k - 1) / 2)});
+        }
+        assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+
+        let mut seed = 47;
+        for n in 0..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            sort_bfs(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            let mut depths: Vec<usize> = vec![0; n];
+            for i in 0..n {
+                if let Some(p) = nodes[i].parent {
+                    depths[i] = depths[p] + 1;
                 }
-            ]
-        );
+            }
+            assert!(depths.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
 
-        let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 3,
-                parent: Some(3),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(3),
-                children: vec![0],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2, 1],
-            },
-        ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 3]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(0),
-                    children: vec![]
+    #[test]
+    fn contiguous_subtrees() {
+        // Returns `true` if every subtree is stored contiguously after its root.
+        fn contiguous(nodes: &mut [Node]) -> bool {
+            let sizes = subtree_sizes(nodes, &|n: &mut Node| &mut n.parent);
+            (0..nodes.len()).all(|j| {
+                let mut ancestor = Some(j);
+                while let Some(i) = ancestor {
+                    if j >= i + sizes[i] {
+                        return false;
+                    }
+                    ancestor = nodes[i].parent;
                 }
-            ]
-        );
+                true
+            })
+        }
 
+        // `sort` alone does not keep subtrees contiguous.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 3,
-                parent: Some(3),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: None,
-                children: vec![],
-            },
-            Node {
-                val: 2,
-                parent: Some(3),
-                children: vec![],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2, 0],
-            },
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![3] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: Some(1), children: vec![] },
         ];
         sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![2, 3]
-                },
-                Node {
-                    val: 1,
-                    parent: None,
-                    children: vec![]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(0),
-                    children: vec![]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(0),
-                    children: vec![]
-                }
-            ]
-        );
+        assert!(!contiguous(&mut nodes));
+        compact_subtrees(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1, 3, 2]);
+        assert!(contiguous(&mut nodes));
+
+        let mut seed = 53;
+        for n in 0..200 {
+            let mut nodes = random_forest(n % 50, &mut seed);
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            compact_subtrees(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_sorted_invariants(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert!(contiguous(&mut nodes));
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let mut nodes = vec![
+            Node { val: 0, parent: None, children: vec![1, 3] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: Some(3), children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+        ];
+        let changes = sort_changes(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(changes, vec![(2, 3), (3, 2)]);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 2, 1, 3]);
+        assert_eq!(sort_changes(&mut nodes, |n| &mut n.parent, |n| &mut n.children), vec![]);
+    }
+
+    #[test]
+    fn topology() {
+        let mut nodes = vec![
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        assert_eq!(classify(&mut nodes, |n| &mut n.parent), Topology::Tree);
+        nodes[1].parent = None;
+        assert_eq!(classify(&mut nodes, |n| &mut n.parent), Topology::Forest(2));
+        nodes[0].parent = Some(1);
+        nodes[1].parent = Some(0);
+        assert_eq!(classify(&mut nodes, |n| &mut n.parent), Topology::Rootless);
+        assert_eq!(classify(&mut nodes[..0], |n| &mut n.parent), Topology::Forest(0));
+    }
+
+    #[test]
+    fn columns() {
+        // The primes example.
+        let mut parents: Vec<Option<usize>> = vec![Some(1), Some(3), Some(3), None, Some(1)];
+        let mut children = vec![vec![], vec![4, 0], vec![], vec![2, 1], vec![]];
+        sort_columns(&mut parents, &mut children);
+        assert_eq!(parents, vec![None, Some(0), Some(0), Some(2), Some(2)]);
+        assert_eq!(children, vec![vec![1, 2], vec![], vec![3, 4], vec![], vec![]]);
+    }
+
+    #[test]
+    fn on_move() {
+        let mut seed = 59;
+        for n in 0..40 {
+            let mut expected = random_forest(n, &mut seed);
+            let mut nodes = expected.clone();
+            let log = sort_record(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+
+            // Keep an external table of values in lockstep with the nodes.
+            let mut table: Vec<u32> = nodes.iter().map(|n| n.val).collect();
+            let mut moves = vec![];
+            let mut placed = vec![false; n];
+            sort_on_move(&mut nodes, |n| &mut n.parent, |n| &mut n.children, |i, j| {
+                assert!(i < j && !placed[j]);
+                placed[j] = true;
+                table.swap(i, j);
+                moves.push((i, j));
+            });
+            assert_eq!(moves, log);
+            assert!(moves.windows(2).all(|w| w[0].0 <= w[1].0));
+            assert_eq!(nodes, expected);
+            assert_eq!(table, nodes.iter().map(|n| n.val).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn optional() {
+        struct Expr {
+            val: char,
+            parent: Option<usize>,
+            args: [Option<usize>; 2],
+        }
+
+        // `-` has no left operand.
+        let mut nodes = vec![
+            Expr { val: 'b', parent: Some(3), args: [None, None] },
+            Expr { val: 'a', parent: Some(2), args: [None, None] },
+            Expr { val: '-', parent: Some(3), args: [None, Some(1)] },
+            Expr { val: '+', parent: None, args: [Some(2), Some(0)] },
+        ];
+        sort_optional(&mut nodes, |n| &mut n.parent, |n| &mut n.args);
+        assert_eq!(nodes.iter().map(|n| n.val).collect::<String>(), "+-ab");
+        assert_eq!(nodes.iter().map(|n| n.parent).collect::<Vec<_>>(),
+            vec![None, Some(0), Some(1), Some(0)]);
+        assert_eq!(nodes.iter().map(|n| n.args).collect::<Vec<_>>(), vec![
+            [Some(1), Some(3)],
+            [None, Some(2)],
+            [None, None],
+            [None, None],
+        ]);
+    }
 
+    #[test]
+    fn reroot_chain() {
+        // a -> b -> c, and a separate root d.
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 3,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 2,
-                parent: Some(2),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(3),
-                children: vec![1, 0],
-            },
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![2],
-            },
+            Node { val: 0, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(1), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2, 3]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(1),
-                    children: vec![]
+        let old = nodes.clone();
+        reroot(&mut nodes, 0, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, old);
+
+        reroot(&mut nodes, 2, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, vec![
+            Node { val: 2, parent: None, children: vec![1] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 0, parent: Some(1), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
+        ]);
+
+        // The old root becomes the last child of the middle node.
+        let mut nodes = old;
+        reroot(&mut nodes, 1, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, vec![
+            Node { val: 1, parent: None, children: vec![1, 2] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+            Node { val: 0, parent: Some(0), children: vec![] },
+            Node { val: 3, parent: None, children: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn violation() {
+        let mut nodes = vec![
+            Node { val: 0, parent: None, children: vec![2, 1] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        assert_eq!(first_violation(&mut nodes, |n| &mut n.children),
+            Some(Violation::SiblingOutOfOrder {parent: 0, earlier: 2, later: 1}));
+        nodes[0].children = vec![1, 2];
+        assert_eq!(first_violation(&mut nodes, |n| &mut n.children), None);
+        nodes.swap(0, 1);
+        nodes[1].children = vec![0, 2];
+        assert_eq!(first_violation(&mut nodes, |n| &mut n.children),
+            Some(Violation::ChildBeforeParent {parent: 1, child: 0}));
+
+        let mut seed = 7;
+        for n in 0..30 {
+            let mut nodes = random_forest(n, &mut seed);
+            assert_eq!(first_violation(&mut nodes, |n| &mut n.children).is_none(),
+                is_sorted(&mut nodes, |n| &mut n.children));
+            sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(first_violation(&mut nodes, |n| &mut n.children), None);
+        }
+    }
+
+    #[test]
+    fn pooled() {
+        let mut seed = 71;
+        for n in 0..40 {
+            let mut expected = random_forest(n, &mut seed);
+            let mut values: Vec<u32> = expected.iter().map(|n| n.val).collect();
+            let mut links = vec![];
+            // Store the children in reverse order with a gap after every range.
+            let mut pool = vec![];
+            for node in expected.iter().rev() {
+                let start = pool.len();
+                pool.extend(node.children.iter().cloned());
+                links.push(Link {parent: node.parent, children: start..pool.len()});
+                pool.push(usize::MAX);
+            }
+            links.reverse();
+
+            sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+            sort_pooled(&mut values, &mut links, &mut pool);
+            assert_eq!(values, expected.iter().map(|n| n.val).collect::<Vec<u32>>());
+            for (link, node) in links.iter().zip(&expected) {
+                assert_eq!(link.parent, node.parent);
+                assert_eq!(pool[link.children.clone()], node.children[..]);
+                assert_eq!(pool[link.children.end], usize::MAX);
+            }
+        }
+    }
+
+    #[test]
+    fn extract() {
+        // The primes example, extracting the subtree of `6`.
+        let mut nodes = vec![
+            Node { val: 2, parent: Some(1), children: vec![] },
+            Node { val: 6, parent: Some(3), children: vec![4, 0] },
+            Node { val: 2, parent: Some(3), children: vec![] },
+            Node { val: 12, parent: None, children: vec![2, 1] },
+            Node { val: 3, parent: Some(1), children: vec![] },
+        ];
+        let original = nodes.clone();
+        let sub = extract_subtree(&mut nodes, 1, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(sub, vec![
+            Node { val: 6, parent: None, children: vec![1, 2] },
+            Node { val: 3, parent: Some(0), children: vec![] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ]);
+        assert_eq!(nodes, original);
+
+        let mut seed = 79;
+        for n in 1..40 {
+            let mut nodes = random_forest(n, &mut seed);
+            let original = nodes.clone();
+            let root = (seed % n as u64) as usize;
+            let mut sub = extract_subtree(&mut nodes, root, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(nodes, original);
+            assert_eq!(sub[0].val, nodes[root].val);
+            assert_eq!(sub[0].parent, None);
+            // Count the nodes that have `root` as an ancestor or are `root`.
+            let size = (0..n).filter(|&k| {
+                let mut k = Some(k);
+                while let Some(i) = k {
+                    if i == root {
+                        return true;
+                    }
+                    k = nodes[i].parent;
                 }
-            ]
-        );
+                false
+            }).count();
+            assert_eq!(sub.len(), size);
+            assert_sorted_invariants(&mut sub, |n| &mut n.parent, |n| &mut n.children);
+            assert!(check_consistency(&mut sub, |n| &mut n.parent, |n| &mut n.children).is_ok());
+        }
+    }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "node `2` is a child of both `0` and `1`")]
+    fn each_shared_child_debug() {
         let mut nodes: Vec<Node> = vec![
-            Node {
-                val: 0,
-                parent: None,
-                children: vec![3, 2],
-            },
-            Node {
-                val: 2,
-                parent: Some(3),
-                children: vec![],
-            },
-            Node {
-                val: 3,
-                parent: Some(0),
-                children: vec![],
-            },
-            Node {
-                val: 1,
-                parent: Some(0),
-                children: vec![1],
-            },
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(0), children: vec![] },
         ];
-        sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
-        assert_eq!(
-            nodes,
-            vec![
-                Node {
-                    val: 0,
-                    parent: None,
-                    children: vec![1, 3]
-                },
-                Node {
-                    val: 1,
-                    parent: Some(0),
-                    children: vec![2]
-                },
-                Node {
-                    val: 2,
-                    parent: Some(1),
-                    children: vec![]
-                },
-                Node {
-                    val: 3,
-                    parent: Some(0),
-                    children: vec![]
-                },
-            ]
-        );
+        sort_each(&mut nodes, |n| &mut n.parent, |n, f| n.children.iter_mut().for_each(f));
     }
 }
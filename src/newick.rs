@@ -0,0 +1,275 @@
+//! Conversion to and from the Newick format, which is used for phylogenetic trees.
+//!
+//! The supported subset has names, nesting and branch lengths, such as `(A:0.1,(B,C)D)E;`.
+//! Names are either unquoted or quoted with `'`, where `''` is a quote inside a name.
+//! Whitespace and comments in brackets are skipped between tokens.
+//! Underscores in unquoted names are kept as they are.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::Node;
+
+/// The name and branch length of a node in a Newick tree.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct NewickLabel {
+    /// The name of the node, which is empty for unnamed nodes.
+    pub name: String,
+    /// The length of the branch to the parent of the node.
+    pub length: Option<f64>,
+}
+
+/// Returned by `from_newick` when the input is not a Newick tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// An unexpected character at a byte offset.
+    Unexpected {
+        /// The byte offset of the character.
+        pos: usize,
+    },
+    /// The input ended before the tree was complete.
+    UnexpectedEnd,
+    /// A branch length that is not a number, at a byte offset.
+    InvalidLength {
+        /// The byte offset of the branch length.
+        pos: usize,
+    },
+}
+
+/// Parses a tree in the Newick format, such as `(A,(B,C));`.
+///
+/// The nodes are stored in depth first order, with the root at index 0,
+/// such that the nodes are sorted.
+/// Children are listed in the order they appear in the input.
+///
+/// ```rust
+/// use tree_mem_sort::{from_newick, to_newick};
+///
+/// let nodes = from_newick("(A:0.5,(B,C)D);").unwrap();
+/// assert_eq!(nodes[0].children, vec![1, 2]);
+/// assert_eq!(nodes[1].value.name, "A");
+/// assert_eq!(nodes[1].value.length, Some(0.5));
+/// assert_eq!(to_newick(&nodes), "(A:0.5,(B,C)D);");
+/// ```
+pub fn from_newick(s: &str) -> Result<Vec<Node<NewickLabel>>, ParseError> {
+    let mut parser = Parser {s, pos: 0};
+    let mut nodes: Vec<Node<NewickLabel>> = vec![];
+    // The nodes whose children are being parsed.
+    let mut open: Vec<usize> = vec![];
+    loop {
+        // Start a new node, which is the root or the next child of the innermost open node.
+        let mut i = nodes.len();
+        let parent = open.last().cloned();
+        if let Some(p) = parent {
+            nodes[p].children.push(i);
+        }
+        nodes.push(Node {value: NewickLabel::default(), parent, children: vec![]});
+        parser.skip()?;
+        if parser.peek() == Some(b'(') {
+            parser.pos += 1;
+            open.push(i);
+            continue;
+        }
+        // Read the label of the node, and the labels of every node that is closed after it.
+        loop {
+            nodes[i].value = parser.label()?;
+            parser.skip()?;
+            match parser.peek() {
+                Some(b',') if !open.is_empty() => {
+                    parser.pos += 1;
+                    break;
+                }
+                Some(b')') => if let Some(p) = open.pop() {
+                    parser.pos += 1;
+                    i = p;
+                    continue;
+                },
+                Some(b';') if open.is_empty() => {
+                    parser.pos += 1;
+                    parser.skip()?;
+                    return match parser.peek() {
+                        None => Ok(nodes),
+                        Some(_) => Err(ParseError::Unexpected {pos: parser.pos}),
+                    };
+                }
+                None => return Err(ParseError::UnexpectedEnd),
+                _ => {}
+            }
+            return Err(ParseError::Unexpected {pos: parser.pos});
+        }
+    }
+}
+
+/// Writes a tree in the Newick format, such as `(A,(B,C));`.
+///
+/// The tree of the first node without a parent is written,
+/// with children in the order they are listed.
+/// Names are quoted when they contain whitespace or characters used by the format.
+/// Since the order of the nodes in memory does not matter, the nodes do not need to be sorted.
+///
+/// Panics if no node is a root.
+pub fn to_newick(nodes: &[Node<NewickLabel>]) -> String {
+    let root = nodes.iter().position(|n| n.parent.is_none()).expect("expected a root");
+    let mut s = String::new();
+    // Every entry is a node and the position of the next child to write.
+    let mut stack: Vec<(usize, usize)> = vec![(root, 0)];
+    while let Some((i, j)) = stack.pop() {
+        match nodes[i].children.get(j) {
+            Some(&ch) => {
+                s.push(if j == 0 {'('} else {','});
+                stack.push((i, j + 1));
+                stack.push((ch, 0));
+            }
+            None => {
+                if j > 0 {
+                    s.push(')');
+                }
+                write_label(&nodes[i].value, &mut s);
+            }
+        }
+    }
+    s.push(';');
+    s
+}
+
+/// Returns `true` if a byte ends an unquoted name or branch length.
+fn is_delimiter(b: u8) -> bool {
+    b"()[]',:;".contains(&b) || b.is_ascii_whitespace()
+}
+
+fn write_label(label: &NewickLabel, s: &mut String) {
+    if label.name.bytes().any(is_delimiter) {
+        s.push('\'');
+        s.push_str(&label.name.replace('\'', "''"));
+        s.push('\'');
+    } else {
+        s.push_str(&label.name);
+    }
+    if let Some(length) = label.length {
+        s.push_str(&format!(":{}", length));
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    /// The byte offset of the next character.
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).cloned()
+    }
+
+    /// Skips whitespace and comments.
+    fn skip(&mut self) -> Result<(), ParseError> {
+        while let Some(b) = self.peek() {
+            if b == b'[' {
+                match self.s[self.pos..].find(']') {
+                    Some(end) => self.pos += end + 1,
+                    None => return Err(ParseError::UnexpectedEnd),
+                }
+            } else if b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads characters up to the next delimiter.
+    fn unquoted(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().map(|b| !is_delimiter(b)).unwrap_or(false) {
+            self.pos += 1;
+        }
+        &self.s[start..self.pos]
+    }
+
+    /// Reads an optional name and an optional branch length.
+    fn label(&mut self) -> Result<NewickLabel, ParseError> {
+        self.skip()?;
+        let mut name = String::new();
+        if self.peek() == Some(b'\'') {
+            self.pos += 1;
+            loop {
+                let end = self.s[self.pos..].find('\'').ok_or(ParseError::UnexpectedEnd)?;
+                name.push_str(&self.s[self.pos..self.pos + end]);
+                self.pos += end + 1;
+                if self.peek() != Some(b'\'') {
+                    break;
+                }
+                // A doubled quote is a quote inside the name.
+                name.push('\'');
+                self.pos += 1;
+            }
+        } else {
+            name.push_str(self.unquoted());
+        }
+
+        self.skip()?;
+        let mut length = None;
+        if self.peek() == Some(b':') {
+            self.pos += 1;
+            self.skip()?;
+            let pos = self.pos;
+            length = Some(self.unquoted().parse().map_err(|_| ParseError::InvalidLength {pos})?);
+        }
+        Ok(NewickLabel {name, length})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TreeNode;
+
+    fn names(nodes: &[Node<NewickLabel>]) -> Vec<&str> {
+        nodes.iter().map(|n| &n.value.name[..]).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let nodes = from_newick("(A,(B,C));").unwrap();
+        assert_eq!(names(&nodes), vec!["", "A", "", "B", "C"]);
+        assert_eq!(nodes.iter().map(|n| n.parent).collect::<Vec<_>>(),
+            vec![None, Some(0), Some(0), Some(2), Some(2)]);
+        assert_eq!(nodes[2].children, vec![3, 4]);
+        assert!(crate::is_sorted(&mut nodes.clone(), Node::children));
+        assert_eq!(to_newick(&nodes), "(A,(B,C));");
+
+        let nodes = from_newick(" ( 'x y':1.5 , 'it''s' [comment] : 2e-3 , ( ) ) root : 0 ; ")
+            .unwrap();
+        assert_eq!(names(&nodes), vec!["root", "x y", "it's", "", ""]);
+        assert_eq!(nodes.iter().map(|n| n.value.length).collect::<Vec<_>>(),
+            vec![Some(0.0), Some(1.5), Some(0.002), None, None]);
+        assert_eq!(to_newick(&nodes), "('x y':1.5,'it''s':0.002,())root:0;");
+
+        assert_eq!(names(&from_newick("A;").unwrap()), vec!["A"]);
+    }
+
+    #[test]
+    fn unsorted() {
+        let mut nodes = from_newick("((B,C)D,A)E;").unwrap();
+        crate::sort_canonical(&mut nodes, Node::parent, Node::children,
+            |n| n.value.name.clone());
+        assert_eq!(names(&nodes), vec!["E", "D", "A", "B", "C"]);
+        assert_eq!(to_newick(&nodes), "((B,C)D,A)E;");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(from_newick("(A,B)"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(from_newick("(A,B;"), Err(ParseError::Unexpected {pos: 4}));
+        assert_eq!(from_newick("A,B;"), Err(ParseError::Unexpected {pos: 1}));
+        assert_eq!(from_newick("(A))B;"), Err(ParseError::Unexpected {pos: 3}));
+        assert_eq!(from_newick("A; B"), Err(ParseError::Unexpected {pos: 3}));
+        assert_eq!(from_newick("(A:x,B);"), Err(ParseError::InvalidLength {pos: 3}));
+        assert_eq!(from_newick("(A:,B);"), Err(ParseError::InvalidLength {pos: 3}));
+        assert_eq!(from_newick("('A,B);"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(from_newick("(A[,B);"), Err(ParseError::UnexpectedEnd));
+    }
+}
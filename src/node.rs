@@ -0,0 +1,190 @@
+//! A node type with a value, a builder that derives children from parents,
+//! and nested trees that are flattened into nodes.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{sort_nodes, validate_tree, ParentCycle, TreeNode};
+
+/// A node of a tree, storing a value with the indices of its parent and children.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node<T> {
+    /// The value of the node.
+    pub value: T,
+    /// The parent of the node.
+    pub parent: Option<usize>,
+    /// The children of the node.
+    pub children: Vec<usize>,
+}
+
+impl<T> TreeNode for Node<T> {
+    type Index = usize;
+    fn parent(&mut self) -> &mut Option<usize> {&mut self.parent}
+    fn children(&mut self) -> &mut [usize] {&mut self.children}
+}
+
+/// Builds a tree from the value and parent of every node.
+///
+/// The children are derived from the parents,
+/// such that the parent and children of the nodes always agree.
+///
+/// ```rust
+/// use tree_mem_sort::TreeBuilder;
+///
+/// let mut builder = TreeBuilder::new();
+/// let leaf = builder.add("leaf", Some(1));
+/// let root = builder.add("root", None);
+/// assert_eq!((leaf, root), (0, 1));
+///
+/// let nodes = builder.build_sorted();
+/// assert_eq!(nodes[0].value, "root");
+/// assert_eq!(nodes[0].children, vec![1]);
+/// assert_eq!(nodes[1].parent, Some(0));
+/// ```
+#[derive(Clone, Debug)]
+pub struct TreeBuilder<T> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> Default for TreeBuilder<T> {
+    fn default() -> Self {
+        TreeBuilder::new()
+    }
+}
+
+impl<T> TreeBuilder<T> {
+    /// Creates a new empty builder.
+    pub fn new() -> Self {
+        TreeBuilder {nodes: Vec::new()}
+    }
+
+    /// Adds a node with a parent, which might be added later.
+    ///
+    /// Returns the index of the node before sorting.
+    pub fn add(&mut self, value: T, parent: Option<usize>) -> usize {
+        self.nodes.push(Node {value, parent, children: Vec::new()});
+        self.nodes.len() - 1
+    }
+
+    /// Derives the children of every node, in the order the nodes were added, and sorts the nodes.
+    ///
+    /// Panics if a parent is out of bounds, or if the parents contain a cycle.
+    pub fn build_sorted(self) -> Vec<Node<T>> {
+        self.try_build_sorted().expect("parent links contain a cycle")
+    }
+
+    /// The same as `build_sorted`, but returns an error if the parents contain a cycle.
+    ///
+    /// Panics if a parent is out of bounds.
+    pub fn try_build_sorted(self) -> Result<Vec<Node<T>>, ParentCycle> {
+        let mut nodes = self.nodes;
+        let n = nodes.len();
+        for (i, node) in nodes.iter().enumerate() {
+            if let Some(p) = node.parent {
+                assert!(p < n, "parent `{}` of node `{}` is out of bounds", p, i);
+            }
+        }
+        validate_tree(&mut nodes, Node::parent)?;
+        for i in 0..n {
+            if let Some(p) = nodes[i].parent {
+                nodes[p].children.push(i);
+            }
+        }
+        sort_nodes(&mut nodes);
+        Ok(nodes)
+    }
+}
+
+/// A tree written as a nested value, which is flattened into nodes.
+///
+/// ```rust
+/// use tree_mem_sort::{node, Node};
+///
+/// let tree = node(12, [node(2, []), node(6, [node(3, []), node(2, [])])]);
+/// let nodes = tree.flatten();
+/// assert_eq!(nodes[0], Node { value: 12, parent: None, children: vec![1, 2] });
+/// assert_eq!(nodes[2], Node { value: 6, parent: Some(0), children: vec![3, 4] });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nested<T> {
+    /// The value of the node.
+    pub value: T,
+    /// The children of the node.
+    pub children: Vec<Nested<T>>,
+}
+
+/// Creates a nested tree from a value and its children.
+pub fn node<T, C>(value: T, children: C) -> Nested<T>
+    where C: IntoIterator<Item = Nested<T>>
+{
+    Nested {value, children: children.into_iter().collect()}
+}
+
+impl<T> Nested<T> {
+    /// Flattens the tree into nodes with parent and children indices.
+    ///
+    /// The nodes are stored in depth first order, with the root at index 0.
+    pub fn flatten(self) -> Vec<Node<T>> {
+        let mut nodes: Vec<Node<T>> = vec![];
+        let mut stack: Vec<(Nested<T>, Option<usize>)> = vec![(self, None)];
+        while let Some((tree, parent)) = stack.pop() {
+            let i = nodes.len();
+            if let Some(p) = parent {
+                nodes[p].children.push(i);
+            }
+            nodes.push(Node {value: tree.value, parent, children: vec![]});
+            stack.extend(tree.children.into_iter().rev().map(|ch| (ch, Some(i))));
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_sorted() {
+        let mut builder = TreeBuilder::new();
+        builder.add('c', Some(2));
+        builder.add('b', Some(2));
+        builder.add('a', None);
+        builder.add('d', Some(0));
+        assert_eq!(builder.build_sorted(), vec![
+            Node { value: 'a', parent: None, children: vec![1, 2] },
+            Node { value: 'c', parent: Some(0), children: vec![3] },
+            Node { value: 'b', parent: Some(0), children: vec![] },
+            Node { value: 'd', parent: Some(1), children: vec![] },
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "parent links contain a cycle")]
+    fn cycle() {
+        let mut builder = TreeBuilder::new();
+        builder.add(0, Some(1));
+        builder.add(1, Some(0));
+        builder.build_sorted();
+    }
+
+    #[test]
+    fn flatten() {
+        let nodes = node('a', [node('b', [node('d', [])]), node('c', [])]).flatten();
+        assert_eq!(nodes, vec![
+            Node { value: 'a', parent: None, children: vec![1, 3] },
+            Node { value: 'b', parent: Some(0), children: vec![2] },
+            Node { value: 'd', parent: Some(1), children: vec![] },
+            Node { value: 'c', parent: Some(0), children: vec![] },
+        ]);
+        assert!(crate::is_sorted(&mut nodes.clone(), Node::children));
+    }
+
+    #[test]
+    fn try_build_cycle() {
+        let mut builder = TreeBuilder::new();
+        builder.add('a', None);
+        builder.add('b', Some(2));
+        builder.add('c', Some(1));
+        assert_eq!(builder.try_build_sorted(), Err(ParentCycle {nodes: vec![1, 2]}));
+    }
+}
@@ -0,0 +1,142 @@
+//! Parallel sorting with `rayon`.
+//!
+//! A pass of the solving phase visits the nodes in order, where each visit might swap
+//! the generator and change the outcome of later visits.
+//! Visits that perform no swaps leave the generator unchanged,
+//! so the work of finding the next node that is out of order is done in parallel,
+//! while the swaps are performed sequentially in the same order as `sort`.
+
+use alloc::vec::Vec;
+
+use rayon::prelude::*;
+
+use crate::{debug_check_shared, retrace, rewrite_indices, visit, Index, SortOrder};
+
+/// The smallest range of nodes searched in parallel.
+///
+/// Ranges of this size are visited sequentially,
+/// since nodes that are out of order are likely to be close together.
+const MIN_WINDOW: usize = 1024;
+
+/// The same algorithm as `sort`, but searches for nodes that are out of order in parallel.
+///
+/// The result is identical to `sort`.
+/// In debug builds, this panics if a node is listed as a child by more than one parent.
+/// This is faster for large trees where most nodes are in order after the first passes.
+pub fn par_sort<T, I, P, C>(nodes: &mut [T], parent: P, children: C)
+    where T: Send,
+          I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I] + Sync
+{
+    debug_check_shared(nodes, &children);
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    if par_solve(nodes, &children, &mut gen) == 1 {
+        // The nodes are already sorted.
+        return;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+}
+
+/// Swaps the generator until it reaches a fixpoint, like `solve` without limits.
+///
+/// Returns the number of passes.
+fn par_solve<T, I, C>(nodes: &mut [T], children: &C, gen: &mut [usize]) -> usize
+    where T: Send,
+          I: Index,
+          C: Fn(&mut T) -> &mut [I] + Sync
+{
+    let mut scratch = Vec::new();
+    let mut passes = 0;
+    loop {
+        passes += 1;
+        let mut changed = false;
+        let mut i = 0;
+        let mut window = MIN_WINDOW;
+        while i < nodes.len() {
+            let end = nodes.len().min(i + window);
+            if window == MIN_WINDOW {
+                let mut swaps = 0;
+                for (j, node) in nodes[i..end].iter_mut().enumerate() {
+                    swaps += visit(i + j, children(node), gen, SortOrder::Ascending, &mut scratch);
+                }
+                if swaps == 0 {
+                    window *= 2;
+                }
+                changed |= swaps > 0;
+                i = end;
+                continue;
+            }
+
+            let found = {
+                let gen: &[usize] = gen;
+                nodes[i..end].par_iter_mut().enumerate()
+                    .position_first(|(j, node)| !in_order(i + j, children(node), gen))
+            };
+            match found {
+                Some(j) => {
+                    let k = i + j;
+                    changed |= visit(k, children(&mut nodes[k]), gen, SortOrder::Ascending,
+                        &mut scratch) > 0;
+                    i = k + 1;
+                    window = MIN_WINDOW;
+                }
+                None => {
+                    i = end;
+                    window *= 2;
+                }
+            }
+        }
+        if !changed {
+            return passes;
+        }
+    }
+}
+
+/// Returns `true` if `visit` would perform no swaps on node `i`.
+fn in_order<I: Index>(i: usize, children: &[I], gen: &[usize]) -> bool {
+    match children.first() {
+        Some(first) if gen[i] > gen[first.to_usize()] => false,
+        _ => children.windows(2).all(|w| gen[w[0].to_usize()] <= gen[w[1].to_usize()]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_forest, Node};
+    use alloc::vec;
+
+    #[test]
+    fn same_as_sort() {
+        for &(n, mut seed) in &[(0, 1), (1, 1), (100, 2), (2_000, 3), (10_000, 4)] {
+            let mut expected = random_forest(n, &mut seed);
+            let mut nodes = expected.clone();
+            crate::sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+            par_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+            assert_eq!(nodes, expected);
+        }
+    }
+
+    #[test]
+    fn already_sorted() {
+        let mut nodes = random_forest(10_000, &mut 5);
+        crate::sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        let expected = nodes.clone();
+        par_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "node `2` is a child of both `0` and `1`")]
+    fn shared_child_debug() {
+        let mut nodes: Vec<Node> = vec![
+            Node { val: 0, parent: None, children: vec![1, 2] },
+            Node { val: 1, parent: Some(0), children: vec![2] },
+            Node { val: 2, parent: Some(0), children: vec![] },
+        ];
+        par_sort(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+    }
+}
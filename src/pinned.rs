@@ -0,0 +1,241 @@
+//! Sorting with nodes pinned to their current positions.
+//!
+//! Pinning turns sorting into scheduling nodes to positions, one node per position,
+//! where every node must be scheduled after the nodes it is constrained by.
+//! A pinned node `i` is released at position `i` and has a deadline at position `i`.
+//! Deadlines are tightened backwards along the constraints and releases forwards,
+//! such that scheduling the available node with the earliest deadline at every position
+//! finds a solution whenever one exists.
+
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::{constraints, retrace, rewrite_indices, solve_generator, Index};
+
+/// Returned by `sort_pinned` when the pinned nodes can not stay in place.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// The same as `sort`, but keeps every node `i` where `pinned[i]` is `true` at index `i`.
+///
+/// When the result of `sort` keeps the pinned nodes in place, that is the result.
+/// Otherwise, the other nodes are placed around the pinned nodes,
+/// preferring the order that `sort` gives.
+///
+/// Pinning makes the problem unsatisfiable when there is not enough room
+/// around a pinned node `i`, which happens when:
+///
+/// - More than `i` nodes must be stored before it, such as its ancestors and earlier siblings
+///   with their descendants
+/// - More than `n - 1 - i` nodes must be stored after it, such as its descendants
+///   and later siblings with their descendants
+/// - Two pinned nodes are in the wrong order, or too close to fit the nodes between them
+///
+/// The nodes are left untouched when an error is returned.
+/// Panics if `pinned` does not have the same length as `nodes`.
+pub fn sort_pinned<T, I, P, C>(
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+    pinned: &[bool],
+) -> Result<(), Unsatisfiable>
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    assert_eq!(pinned.len(), nodes.len(), "expected one pin flag per node");
+    let mut gen = solve_generator(nodes, &children);
+    if pinned.iter().enumerate().any(|(i, &p)| p && gen[i] != i) {
+        gen = schedule(nodes, &children, pinned, &gen).ok_or(Unsatisfiable)?;
+    }
+    rewrite_indices(nodes, &parent, &children, &gen);
+    retrace(nodes, &mut gen);
+    Ok(())
+}
+
+/// Assigns a new position to every node, keeping pinned nodes in place.
+///
+/// Ties are broken by the preferred positions in `prefer`.
+/// Returns `None` if there is no solution.
+fn schedule<T, I, C>(nodes: &mut [T], children: &C, pinned: &[bool], prefer: &[usize])
+    -> Option<Vec<usize>>
+    where I: Index,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let (offsets, targets) = constraints(nodes, children);
+    let mut indegree = vec![0; n];
+    for &b in &targets {
+        indegree[b] += 1;
+    }
+
+    // Find an order where every node comes after the nodes it is constrained by.
+    let mut order: Vec<usize> = (0..n).filter(|&i| indegree[i] == 0).collect();
+    let mut remaining = indegree.clone();
+    let mut k = 0;
+    while let Some(&a) = order.get(k) {
+        k += 1;
+        for &b in &targets[offsets[a]..offsets[a + 1]] {
+            remaining[b] -= 1;
+            if remaining[b] == 0 {
+                order.push(b);
+            }
+        }
+    }
+    if order.len() < n {
+        // The constraints contain a cycle.
+        return None;
+    }
+
+    let mut release: Vec<usize> = (0..n).map(|i| if pinned[i] {i} else {0}).collect();
+    let mut deadline: Vec<usize> = (0..n).map(|i| if pinned[i] {i} else {n - 1}).collect();
+    for &a in &order {
+        for &b in &targets[offsets[a]..offsets[a + 1]] {
+            release[b] = release[b].max(release[a] + 1);
+        }
+    }
+    for &a in order.iter().rev() {
+        for &b in &targets[offsets[a]..offsets[a + 1]] {
+            deadline[a] = deadline[a].min(deadline[b].checked_sub(1)?);
+        }
+        if release[a] > deadline[a] {
+            return None;
+        }
+    }
+
+    // Schedule the available node with the earliest deadline at every position.
+    let mut gen = vec![0; n];
+    let mut waiting = BinaryHeap::new();
+    let mut ready = BinaryHeap::new();
+    for i in 0..n {
+        if indegree[i] == 0 {
+            waiting.push(Reverse((release[i], i)));
+        }
+    }
+    for t in 0..n {
+        while let Some(&Reverse((r, i))) = waiting.peek() {
+            if r > t {
+                break;
+            }
+            waiting.pop();
+            ready.push(Reverse((deadline[i], prefer[i], i)));
+        }
+        let Reverse((d, _, a)) = ready.pop()?;
+        if d < t {
+            return None;
+        }
+        gen[a] = t;
+        for &b in &targets[offsets[a]..offsets[a + 1]] {
+            indegree[b] -= 1;
+            if indegree[b] == 0 {
+                waiting.push(Reverse((release[b], b)));
+            }
+        }
+    }
+    Some(gen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_forest, Lcg, Node};
+
+    fn vals(nodes: &[Node]) -> Vec<u32> {
+        nodes.iter().map(|n| n.val).collect()
+    }
+
+    #[test]
+    fn pinned() {
+        let nodes = vec![
+            Node { val: 2, parent: Some(2), children: vec![] },
+            Node { val: 1, parent: Some(2), children: vec![] },
+            Node { val: 0, parent: None, children: vec![1, 0] },
+        ];
+
+        let mut sorted = nodes.clone();
+        let res = sort_pinned(&mut sorted, |n| &mut n.parent, |n| &mut n.children,
+            &[false; 3]);
+        assert_eq!(res, Ok(()));
+        assert_eq!(vals(&sorted), vec![0, 1, 2]);
+
+        let mut sorted = nodes.clone();
+        let res = sort_pinned(&mut sorted, |n| &mut n.parent, |n| &mut n.children,
+            &[true, false, false]);
+        assert_eq!(res, Err(Unsatisfiable));
+        assert_eq!(sorted, nodes);
+
+        let mut sorted = vec![
+            Node { val: 0, parent: None, children: vec![2] },
+            Node { val: 2, parent: None, children: vec![] },
+            Node { val: 1, parent: Some(0), children: vec![] },
+        ];
+        let res = sort_pinned(&mut sorted, |n| &mut n.parent, |n| &mut n.children,
+            &[false, false, true]);
+        assert_eq!(res, Ok(()));
+        assert_eq!(vals(&sorted), vec![0, 2, 1]);
+    }
+
+    /// Returns `true` if some permutation keeps the pinned nodes in place.
+    fn feasible(nodes: &mut [Node], pinned: &[bool]) -> bool {
+        fn search(
+            offsets: &[usize],
+            targets: &[usize],
+            pinned: &[bool],
+            gen: &mut Vec<usize>,
+            used: &mut Vec<bool>,
+        ) -> bool {
+            let t = used.iter().filter(|&&u| u).count();
+            if t == gen.len() {
+                return (0..gen.len()).all(|a| {
+                    targets[offsets[a]..offsets[a + 1]].iter().all(|&b| gen[a] < gen[b])
+                });
+            }
+            for a in 0..gen.len() {
+                if used[a] || pinned[a] && a != t || !pinned[a] && pinned[t] {
+                    continue;
+                }
+                used[a] = true;
+                gen[a] = t;
+                if search(offsets, targets, pinned, gen, used) {
+                    return true;
+                }
+                used[a] = false;
+            }
+            false
+        }
+
+        let n = nodes.len();
+        let (offsets, targets) = constraints(nodes, &|n: &mut Node| &mut n.children[..]);
+        search(&offsets, &targets, pinned, &mut vec![0; n], &mut vec![false; n])
+    }
+
+    #[test]
+    fn same_as_search() {
+        let mut rng = Lcg(3);
+        let mut outcomes = [0; 2];
+        for _ in 0..300 {
+            let n = 1 + rng.next(6);
+            let mut nodes = random_forest(n, &mut rng.0);
+            for (i, node) in nodes.iter_mut().enumerate() {
+                node.val = i as u32;
+            }
+            let pinned: Vec<bool> = (0..n).map(|_| rng.next(3) == 0).collect();
+
+            let expected = feasible(&mut nodes, &pinned);
+            let mut sorted = nodes.clone();
+            let res = sort_pinned(&mut sorted, |n| &mut n.parent, |n| &mut n.children, &pinned);
+            assert_eq!(res.is_ok(), expected);
+            outcomes[expected as usize] += 1;
+            if res.is_ok() {
+                for i in (0..n).filter(|&i| pinned[i]) {
+                    assert_eq!(sorted[i].val, i as u32);
+                }
+                assert!(crate::is_sorted(&mut sorted, |n| &mut n.children));
+            }
+        }
+        // Both outcomes are covered.
+        assert!(outcomes[0] > 0 && outcomes[1] > 0, "{:?}", outcomes);
+    }
+}
@@ -0,0 +1,152 @@
+//! Interoperability with trees built from `Rc<RefCell<N>>` handles.
+//!
+//! A pointer tree has no memory layout to sort, so it is flattened into nodes
+//! that are already sorted, which can be used with the rest of this library.
+//! The order of siblings is the only order a pointer tree has,
+//! which `sort_rc` canonicalizes by sorting every child list by a key.
+
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::{sort_nodes, Node};
+
+/// Flattens a tree of `Rc<RefCell<N>>` handles into sorted nodes with indices.
+///
+/// The value of every node is a handle to the original node.
+/// The nodes are stored in depth first order with the root at index 0,
+/// such that every child is greater than their parent,
+/// and every sibling is greater than previous siblings.
+/// The result is the same as flattening and calling `sort`.
+///
+/// A handle that is listed as a child more than once becomes several nodes
+/// sharing the same handle.
+/// The handles must not contain a cycle, or this runs out of memory.
+pub fn flatten_rc<N, C>(root: &Rc<RefCell<N>>, children: C) -> Vec<Node<Rc<RefCell<N>>>>
+    where C: Fn(&N) -> &[Rc<RefCell<N>>]
+{
+    flatten(root, |handle, list| list.extend(children(&handle.borrow()).iter().cloned()))
+}
+
+/// Sorts the children of every handle in a tree of `Rc<RefCell<N>>` handles by a key,
+/// and returns the sorted nodes with indices.
+///
+/// The tree is flattened like `flatten_rc`, the children of every node are sorted by the key,
+/// with siblings of equal keys keeping their order, and the nodes are sorted with `sort`.
+/// Then the child vector of every handle is rebuilt in the sorted order of its children,
+/// such that flattening the tree again gives the same order of siblings.
+///
+/// A handle that is listed as a child more than once becomes several nodes
+/// with the same children, so its child vector is rebuilt the same way for each of them.
+/// The handles must not contain a cycle, or this runs out of memory.
+///
+/// ```rust
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use tree_mem_sort::sort_rc;
+///
+/// struct Expr {
+///     val: u32,
+///     args: Vec<Rc<RefCell<Expr>>>,
+/// }
+///
+/// let leaf = |val| Rc::new(RefCell::new(Expr {val, args: vec![]}));
+/// let root = Rc::new(RefCell::new(Expr {val: 0, args: vec![leaf(3), leaf(1), leaf(2)]}));
+/// let nodes = sort_rc(&root, |e| &mut e.args, |e| e.val);
+/// let vals: Vec<u32> = root.borrow().args.iter().map(|e| e.borrow().val).collect();
+/// assert_eq!(vals, vec![1, 2, 3]);
+/// assert_eq!(nodes[1].value.borrow().val, 1);
+/// ```
+pub fn sort_rc<N, C, K, F>(root: &Rc<RefCell<N>>, children: C, key: F)
+    -> Vec<Node<Rc<RefCell<N>>>>
+    where C: Fn(&mut N) -> &mut Vec<Rc<RefCell<N>>>,
+          K: Ord,
+          F: Fn(&N) -> K
+{
+    let mut nodes = flatten(root, |handle, list| {
+        list.extend(children(&mut handle.borrow_mut()).iter().cloned())
+    });
+    let keys: Vec<K> = nodes.iter().map(|n| key(&n.value.borrow())).collect();
+    for node in &mut nodes {
+        node.children.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+    }
+    sort_nodes(&mut nodes);
+    for node in &nodes {
+        let list: Vec<Rc<RefCell<N>>> =
+            node.children.iter().map(|&ch| nodes[ch].value.clone()).collect();
+        *children(&mut node.value.borrow_mut()) = list;
+    }
+    nodes
+}
+
+/// Flattens a tree in depth first order,
+/// where `push_children` pushes the children of a handle to a list.
+fn flatten<N, F>(root: &Rc<RefCell<N>>, push_children: F) -> Vec<Node<Rc<RefCell<N>>>>
+    where F: Fn(&RefCell<N>, &mut Vec<Rc<RefCell<N>>>)
+{
+    let mut nodes: Vec<Node<Rc<RefCell<N>>>> = vec![];
+    let mut stack: Vec<(Rc<RefCell<N>>, Option<usize>)> = vec![(root.clone(), None)];
+    let mut list: Vec<Rc<RefCell<N>>> = vec![];
+    while let Some((handle, parent)) = stack.pop() {
+        let i = nodes.len();
+        if let Some(p) = parent {
+            nodes[p].children.push(i);
+        }
+        push_children(&handle, &mut list);
+        stack.extend(list.drain(..).rev().map(|ch| (ch, Some(i))));
+        nodes.push(Node {value: handle, parent, children: vec![]});
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Expr {
+        val: u32,
+        args: Vec<Rc<RefCell<Expr>>>,
+    }
+
+    fn expr(val: u32, args: Vec<Rc<RefCell<Expr>>>) -> Rc<RefCell<Expr>> {
+        Rc::new(RefCell::new(Expr {val, args}))
+    }
+
+    #[test]
+    fn flatten() {
+        let two = expr(2, vec![]);
+        let root = expr(12, vec![two.clone(), expr(6, vec![expr(3, vec![]), two.clone()])]);
+        let mut nodes = flatten_rc(&root, |e| &e.args);
+        let vals: Vec<u32> = nodes.iter().map(|n| n.value.borrow().val).collect();
+        assert_eq!(vals, vec![12, 2, 6, 3, 2]);
+        assert_eq!(nodes.iter().map(|n| n.parent).collect::<Vec<_>>(),
+            vec![None, Some(0), Some(0), Some(2), Some(2)]);
+        assert_eq!(nodes[0].children, vec![1, 2]);
+        assert_eq!(nodes[2].children, vec![3, 4]);
+        assert!(Rc::ptr_eq(&nodes[1].value, &nodes[4].value));
+        assert!(crate::is_sorted(&mut nodes, |n| &mut n.children));
+    }
+
+    #[test]
+    fn sort_children() {
+        let two = expr(2, vec![expr(9, vec![]), expr(8, vec![])]);
+        let root = expr(12, vec![expr(6, vec![two.clone(), expr(3, vec![])]), two.clone()]);
+        let nodes = sort_rc(&root, |e| &mut e.args, |e| e.val);
+        let vals: Vec<u32> = nodes.iter().map(|n| n.value.borrow().val).collect();
+        assert_eq!(vals.len(), 9);
+        assert!(crate::is_sorted(&mut nodes.clone(), |n| &mut n.children));
+        for node in &nodes {
+            let args: Vec<u32> = node.value.borrow().args.iter().map(|e| e.borrow().val).collect();
+            let children: Vec<u32> = node.children.iter().map(|&ch| vals[ch]).collect();
+            assert_eq!(args, children);
+            assert!(args.windows(2).all(|w| w[0] <= w[1]));
+        }
+        assert_eq!(vals[..2], [12, 2]);
+
+        // Flattening again gives the sorted siblings in depth first order.
+        let flat = flatten_rc(&root, |e| &e.args);
+        let vals: Vec<u32> = flat.iter().map(|n| n.value.borrow().val).collect();
+        assert_eq!(vals, vec![12, 2, 8, 9, 6, 2, 8, 9, 3]);
+    }
+}
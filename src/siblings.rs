@@ -0,0 +1,164 @@
+//! Sibling ordering for nodes with many children.
+//!
+//! Checking all pairs of children takes quadratic time in the number of children,
+//! even when only a few of the pairs are out of order.
+//! For a fixed child `a`, the pairs that swap the generator are exactly
+//! the later siblings that are less than the current value of `a`, from left to right.
+//! These are found by searching a segment tree of minimum keys,
+//! which performs the same swaps in the same order as checking all pairs.
+
+use alloc::vec::Vec;
+
+use crate::{Index, SortOrder};
+
+/// The minimum number of children where the segment tree is used.
+pub(crate) const WIDE: usize = 32;
+
+/// Checks the children of node `i` against their parent and each other,
+/// using a segment tree to find the siblings that are out of order.
+///
+/// Returns `None` if the children are not unique or contain `i`,
+/// in which case the generator is left unchanged.
+/// The segment tree is stored in `tree`, which is reused between visits to avoid allocating.
+pub(crate) fn visit<I: Index>(
+    i: usize,
+    children: &[I],
+    gen: &mut [usize],
+    order: SortOrder,
+    tree: &mut Vec<usize>,
+) -> Option<usize> {
+    // Descending order flips the keys, such that a sibling is out of order
+    // when its key is less than the key of an earlier sibling.
+    let key = |x: usize| match order {
+        SortOrder::Ascending => x,
+        SortOrder::Descending => usize::MAX - x,
+    };
+    let size = children.len().next_power_of_two();
+    tree.clear();
+    tree.resize(2 * size, usize::MAX);
+    if !unique(i, children, &mut tree[size..size + children.len()]) {
+        return None;
+    }
+    for (j, ch) in children.iter().enumerate() {
+        tree[size + j] = key(gen[ch.to_usize()]);
+    }
+    for x in (1..size).rev() {
+        tree[x] = tree[2 * x].min(tree[2 * x + 1]);
+    }
+
+    let mut swaps = 0;
+    for (j, a) in children.iter().enumerate() {
+        let a = a.to_usize();
+        // Store child after its parent.
+        if gen[i] > gen[a] {
+            gen.swap(i, a);
+            swaps += 1;
+        }
+        // Store children in sorted order.
+        // The key of `j` is never read again, so only later siblings are updated.
+        let mut k = j + 1;
+        while let Some(f) = first_below(tree, k, key(gen[a])) {
+            let b = children[f].to_usize();
+            gen.swap(a, b);
+            swaps += 1;
+            set(tree, f, key(gen[b]));
+            k = f + 1;
+        }
+    }
+    Some(swaps)
+}
+
+/// Returns `true` if the children are unique and do not contain `i`,
+/// using `buf` of the same length as the children to sort their indices.
+///
+/// Since the generator is a permutation, this is the same as the values in the generator
+/// being unique, which does not change when swapping the generator.
+fn unique<I: Index>(i: usize, children: &[I], buf: &mut [usize]) -> bool {
+    for (x, ch) in buf.iter_mut().zip(children) {
+        *x = ch.to_usize();
+    }
+    buf.sort_unstable();
+    buf.windows(2).all(|w| w[0] != w[1]) && buf.binary_search(&i).is_err()
+}
+
+/// Returns the first leaf at or after `lo` with a key less than `t`.
+fn first_below(tree: &[usize], lo: usize, t: usize) -> Option<usize> {
+    let size = tree.len() / 2;
+    if lo >= size {
+        return None;
+    }
+    let mut x = size + lo;
+    loop {
+        if tree[x] < t {
+            while x < size {
+                x *= 2;
+                if tree[x] >= t {
+                    x += 1;
+                }
+            }
+            return Some(x - size);
+        }
+        // Move to the next range to the right.
+        while x & 1 == 1 {
+            x >>= 1;
+        }
+        if x == 0 {
+            return None;
+        }
+        x += 1;
+    }
+}
+
+/// Sets the key of leaf `j` and updates the minimums above it.
+fn set(tree: &mut [usize], j: usize, val: usize) {
+    let mut x = tree.len() / 2 + j;
+    tree[x] = val;
+    while x > 1 {
+        x >>= 1;
+        tree[x] = tree[2 * x].min(tree[2 * x + 1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::test_util::Lcg;
+
+    #[test]
+    fn same_swaps_as_pairs() {
+        let mut rng = Lcg(7);
+        // Reuse the segment tree between visits, like the callers do.
+        let mut tree = vec![];
+        for &order in &[SortOrder::Ascending, SortOrder::Descending] {
+            for m in 1..100 {
+                let n = m + 1 + rng.next(8);
+                let mut gen: Vec<usize> = (0..n).collect();
+                for x in (1..n).rev() {
+                    gen.swap(x, rng.next(x + 1));
+                }
+                let i = rng.next(n);
+                let mut children: Vec<usize> = (0..n).filter(|&x| x != i).collect();
+                for x in (1..children.len()).rev() {
+                    children.swap(x, rng.next(x + 1));
+                }
+                children.truncate(m);
+
+                let mut expected = gen.clone();
+                let expected_swaps = crate::visit_pairs(i, &children, &mut expected, order);
+                let swaps = visit(i, &children, &mut gen, order, &mut tree);
+                assert_eq!(swaps, Some(expected_swaps));
+                assert_eq!(gen, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn not_unique() {
+        let mut gen = vec![2, 1, 0];
+        let mut tree = vec![];
+        assert_eq!(visit(0, &[1usize, 2, 1], &mut gen, SortOrder::Ascending, &mut tree), None);
+        assert_eq!(visit(0, &[1usize, 0], &mut gen, SortOrder::Ascending, &mut tree), None);
+        assert_eq!(gen, vec![2, 1, 0]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Sorting in steps with a budget, for spreading the work over time.
+
+use alloc::vec::Vec;
+
+use crate::{visit, Index, SortOrder};
+
+/// Whether `sort_step` finished sorting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// The nodes are sorted.
+    Done,
+    /// More work remains, so `sort_step` must be called again.
+    More,
+}
+
+/// The phase of a sort in steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StepPhase {
+    Start,
+    Solve,
+    Rewrite,
+    Retrace,
+    Done,
+}
+
+/// The state of a sort in steps, see `sort_step`.
+#[derive(Clone, Debug)]
+pub struct SortState {
+    gen: Vec<usize>,
+    /// The buffer for visiting nodes with many children.
+    scratch: Vec<usize>,
+    phase: StepPhase,
+    /// The next node to process in the current phase.
+    next: usize,
+    /// Whether the current pass of the solving phase has swapped the generator.
+    changed: bool,
+    /// Whether the current pass is the first pass of the solving phase.
+    first_pass: bool,
+}
+
+impl Default for SortState {
+    fn default() -> Self {
+        SortState::new()
+    }
+}
+
+impl SortState {
+    /// Creates a state for starting a new sort.
+    pub fn new() -> Self {
+        SortState {
+            gen: Vec::new(),
+            scratch: Vec::new(),
+            phase: StepPhase::Start,
+            next: 0,
+            changed: false,
+            first_pass: true,
+        }
+    }
+}
+
+/// Performs at most about `budget` units of work of the same algorithm as `sort`.
+///
+/// A unit of work is visiting one node and checking its children,
+/// rewriting the indices of one node, or swapping one node.
+/// The budget is checked between units, and visiting a node with many children counts as
+/// one unit per child, so a call might use a little more than `budget`.
+/// A budget of `0` is treated as `1`, such that every call makes progress.
+///
+/// Returns `Progress::More` until the nodes are sorted.
+/// The nodes, parents and children must not be changed between calls,
+/// and the result is the same as `sort` when the last call returns `Progress::Done`.
+/// Between calls, the indices might be rewritten before the nodes are moved,
+/// so the nodes should not be used as a tree until sorting is done.
+/// Use a new `SortState` for every sort.
+pub fn sort_step<T, I, P, C>(
+    state: &mut SortState,
+    nodes: &mut [T],
+    parent: P,
+    children: C,
+    budget: usize,
+) -> Progress
+    where I: Index,
+          P: Fn(&mut T) -> &mut Option<I>,
+          C: Fn(&mut T) -> &mut [I]
+{
+    let n = nodes.len();
+    let budget = budget.max(1);
+    let mut work = 0;
+    while work < budget {
+        match state.phase {
+            StepPhase::Start => {
+                state.gen.clear();
+                state.gen.extend(0..n);
+                state.phase = StepPhase::Solve;
+            }
+            StepPhase::Solve => {
+                if state.next == n {
+                    if !state.changed {
+                        // When the first pass made no changes, the nodes are already sorted.
+                        state.phase = if state.first_pass {StepPhase::Done}
+                                      else {StepPhase::Rewrite};
+                    }
+                    state.next = 0;
+                    state.changed = false;
+                    state.first_pass = false;
+                    continue;
+                }
+                let i = state.next;
+                let children = children(&mut nodes[i]);
+                work += 1 + children.len();
+                state.changed |= visit(i, children, &mut state.gen, SortOrder::Ascending,
+                    &mut state.scratch) > 0;
+                state.next += 1;
+            }
+            StepPhase::Rewrite => {
+                if state.next == n {
+                    state.next = 0;
+                    state.phase = StepPhase::Retrace;
+                    continue;
+                }
+                let gen = &state.gen;
+                let node = &mut nodes[state.next];
+                let p = parent(node);
+                *p = p.map(|p| I::from_usize(gen[p.to_usize()]));
+                for ch in children(node) {
+                    *ch = I::from_usize(gen[ch.to_usize()]);
+                }
+                work += 1;
+                state.next += 1;
+            }
+            StepPhase::Retrace => {
+                if state.next == n {
+                    state.phase = StepPhase::Done;
+                    continue;
+                }
+                // Retrace one swap at a time, see `retrace`.
+                let i = state.next;
+                let j = state.gen[i];
+                if j == i {
+                    state.next += 1;
+                } else {
+                    nodes.swap(i, j);
+                    state.gen.swap(i, j);
+                }
+                work += 1;
+            }
+            StepPhase::Done => return Progress::Done,
+        }
+    }
+    if state.phase == StepPhase::Done {Progress::Done} else {Progress::More}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::random_forest;
+
+    #[test]
+    fn same_as_sort() {
+        let mut seed: u64 = 43;
+        for n in 0..30 {
+            let mut expected = random_forest(n, &mut seed);
+            // A budget of 0 still makes progress.
+            let budget = n % 5;
+            let mut nodes = expected.clone();
+            crate::sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+            let mut state = SortState::new();
+            let mut calls = 0;
+            while sort_step(&mut state, &mut nodes, |n| &mut n.parent, |n| &mut n.children,
+                budget) == Progress::More
+            {
+                calls += 1;
+                assert!(calls < 10_000);
+            }
+            assert_eq!(nodes, expected);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Sorting nodes in collections other than slices.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::ops::IndexMut;
+
+use crate::{sort_unbounded, Index};
+
+/// A collection of nodes that can be indexed and swapped in constant time,
+/// without requiring the nodes to be stored contiguously.
+///
+/// The nodes are indexed from `0` to `len() - 1`.
+pub trait Storage: IndexMut<usize> {
+    /// Returns the number of nodes.
+    fn len(&self) -> usize;
+    /// Returns `true` if there are no nodes.
+    fn is_empty(&self) -> bool {self.len() == 0}
+    /// Swaps the nodes at `i` and `j`.
+    fn swap(&mut self, i: usize, j: usize);
+}
+
+impl<T> Storage for [T] {
+    fn len(&self) -> usize {<[T]>::len(self)}
+    fn swap(&mut self, i: usize, j: usize) {<[T]>::swap(self, i, j)}
+}
+
+impl<T> Storage for Vec<T> {
+    fn len(&self) -> usize {Vec::len(self)}
+    fn swap(&mut self, i: usize, j: usize) {<[T]>::swap(self, i, j)}
+}
+
+impl<T> Storage for VecDeque<T> {
+    fn len(&self) -> usize {VecDeque::len(self)}
+    fn swap(&mut self, i: usize, j: usize) {VecDeque::swap(self, i, j)}
+}
+
+/// The same algorithm as `sort`, but for nodes in any collection implementing `Storage`,
+/// such as a `VecDeque` or an arena that is not stored contiguously.
+///
+/// In debug builds, this panics if a node is listed as a child by more than one parent.
+///
+/// ```rust
+/// use std::collections::VecDeque;
+/// use tree_mem_sort::sort_storage;
+///
+/// struct Node {
+///     val: u32,
+///     parent: Option<usize>,
+///     children: Vec<usize>,
+/// }
+///
+/// let mut nodes: VecDeque<Node> = VecDeque::new();
+/// nodes.push_back(Node { val: 1, parent: Some(1), children: vec![] });
+/// nodes.push_back(Node { val: 0, parent: None, children: vec![0] });
+/// sort_storage(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+/// assert_eq!(nodes[0].val, 0);
+/// assert_eq!(nodes[0].children, vec![1]);
+/// ```
+pub fn sort_storage<S, I, P, C>(nodes: &mut S, parent: P, children: C)
+    where S: Storage + ?Sized,
+          I: Index,
+          P: Fn(&mut S::Output) -> &mut Option<I>,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    let mut gen: Vec<usize> = (0..nodes.len()).collect();
+    sort_unbounded(nodes, &parent, &children, &mut gen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[derive(Clone, PartialEq, Debug)]
+    struct Node {
+        val: u32,
+        parent: Option<usize>,
+        children: Vec<usize>,
+    }
+
+    #[test]
+    fn same_as_sort() {
+        let mut expected = vec![
+            Node { val: 2, parent: Some(1), children: vec![] },
+            Node { val: 6, parent: Some(3), children: vec![4, 0] },
+            Node { val: 2, parent: Some(3), children: vec![] },
+            Node { val: 12, parent: None, children: vec![2, 1] },
+            Node { val: 3, parent: Some(1), children: vec![] },
+        ];
+        // Wrap the nodes around the end of the buffer, such that they are not contiguous.
+        let mut nodes: VecDeque<Node> = VecDeque::with_capacity(expected.len());
+        for node in expected[..2].iter().rev() {
+            nodes.push_front(node.clone());
+        }
+        nodes.extend(expected[2..].iter().cloned());
+        assert!(!nodes.as_slices().1.is_empty());
+        crate::sort(&mut expected, |n| &mut n.parent, |n| &mut n.children);
+        sort_storage(&mut nodes, |n| &mut n.parent, |n| &mut n.children);
+        assert_eq!(nodes, expected);
+        assert_eq!(expected.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![12, 2, 6, 3, 2]);
+    }
+}
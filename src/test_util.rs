@@ -0,0 +1,51 @@
+//! Fixtures shared by the tests of several modules.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A node with a value, used by the tests.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Node {
+    pub val: u32,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// Generates pseudo-random numbers without external dependencies.
+pub(crate) struct Lcg(pub u64);
+
+impl Lcg {
+    /// Returns a number in `0..n`.
+    pub fn next(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+}
+
+/// Generates a forest with `n` nodes in a pseudo-random order,
+/// where the value of every node is the order it was created in.
+///
+/// Every node with a parent has a parent created before it,
+/// and is inserted at a random position among the children of the parent.
+pub(crate) fn random_forest(n: usize, seed: &mut u64) -> Vec<Node> {
+    let mut rng = Lcg(*seed);
+    let mut pos: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        pos.swap(i, rng.next(i + 1));
+    }
+    let mut nodes: Vec<Node> = (0..n).map(|k| Node {
+        val: k as u32,
+        parent: None,
+        children: vec![],
+    }).collect();
+    for k in 1..n {
+        if rng.next(8) > 0 {
+            let p = rng.next(k);
+            nodes[pos[k]].parent = Some(pos[p]);
+            let len = nodes[pos[p]].children.len();
+            nodes[pos[p]].children.insert(rng.next(len + 1), pos[k]);
+        }
+    }
+    *seed = rng.0;
+    nodes
+}
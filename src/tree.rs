@@ -0,0 +1,130 @@
+//! A wrapper that owns nodes together with their accessors.
+
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+use crate::Index;
+
+/// Stores nodes together with the accessors for parent and children.
+///
+/// This avoids repeating the accessors at every call site.
+/// Dereferences to the slice of nodes.
+pub struct Tree<T, P, C> {
+    nodes: Vec<T>,
+    parent: P,
+    children: C,
+}
+
+impl<T, P, C> Tree<T, P, C> {
+    /// Creates a new tree where every node has maximum one parent.
+    pub fn new<I>(nodes: Vec<T>, parent: P, children: C) -> Self
+        where I: Index,
+              P: Fn(&mut T) -> &mut Option<I>,
+              C: Fn(&mut T) -> &mut [I]
+    {
+        Tree {nodes, parent, children}
+    }
+
+    /// Creates a new tree where nodes can have multiple parents.
+    pub fn new_dag<I>(nodes: Vec<T>, parents: P, children: C) -> Self
+        where I: Index,
+              P: Fn(&mut T) -> &mut [I],
+              C: Fn(&mut T) -> &mut [I]
+    {
+        Tree {nodes, parent: parents, children}
+    }
+
+    /// Returns the nodes.
+    pub fn into_nodes(self) -> Vec<T> {
+        self.nodes
+    }
+
+    /// Sorts the nodes, see `sort`.
+    pub fn sort<I>(&mut self)
+        where I: Index,
+              P: Fn(&mut T) -> &mut Option<I>,
+              C: Fn(&mut T) -> &mut [I]
+    {
+        crate::sort(&mut self.nodes, &self.parent, &self.children)
+    }
+
+    /// Sorts the nodes, see `sort_dag`.
+    pub fn sort_dag<I>(&mut self)
+        where I: Index,
+              P: Fn(&mut T) -> &mut [I],
+              C: Fn(&mut T) -> &mut [I]
+    {
+        crate::sort_dag(&mut self.nodes, &self.parent, &self.children)
+    }
+}
+
+impl<T, P, C> Deref for Tree<T, P, C> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.nodes
+    }
+}
+
+impl<T, P, C> DerefMut for Tree<T, P, C> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[derive(PartialEq, Debug)]
+    struct Node {
+        val: u32,
+        parent: Option<usize>,
+        children: Vec<usize>,
+    }
+
+    #[derive(PartialEq, Debug)]
+    struct DagNode {
+        val: u32,
+        parents: Vec<usize>,
+        children: Vec<usize>,
+    }
+
+    #[test]
+    fn sort() {
+        let nodes = vec![
+            Node { val: 1, parent: Some(1), children: vec![] },
+            Node { val: 0, parent: None, children: vec![0] },
+        ];
+        let mut tree = Tree::new(nodes, |n| &mut n.parent, |n| &mut n.children);
+        tree.sort();
+        assert_eq!(tree.iter().map(|n| n.val).collect::<Vec<u32>>(), vec![0, 1]);
+        assert_eq!(
+            tree.into_nodes(),
+            vec![
+                Node { val: 0, parent: None, children: vec![1] },
+                Node { val: 1, parent: Some(0), children: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_dag() {
+        let nodes = vec![
+            DagNode { val: 2, parents: vec![1, 2], children: vec![] },
+            DagNode { val: 1, parents: vec![2], children: vec![0] },
+            DagNode { val: 0, parents: vec![], children: vec![1, 0] },
+        ];
+        let mut tree = Tree::new_dag(nodes, |n| &mut n.parents, |n| &mut n.children);
+        tree.sort_dag();
+        assert_eq!(
+            tree.into_nodes(),
+            vec![
+                DagNode { val: 0, parents: vec![], children: vec![1, 2] },
+                DagNode { val: 1, parents: vec![0], children: vec![2] },
+                DagNode { val: 2, parents: vec![1, 0], children: vec![] },
+            ]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Solving passes that only revisit the nodes near earlier swaps.
+//!
+//! Visiting a node only reads and swaps the generator at the node and its children.
+//! When a visit made no swaps and none of these entries have changed since,
+//! visiting the node again makes no swaps either.
+//! Therefore, once few nodes swap the generator in a pass,
+//! every pass only visits the nodes that are marked by a swap since their last visit,
+//! in increasing order, which performs the same swaps in the same order as visiting all nodes.
+//! This turns deep trees that need many passes with few swaps each
+//! from quadratic time into nearly linear time.
+//!
+//! A swap at node `i` marks `i`, its children and the parents of all these nodes,
+//! which are found by inverting the children lists when marking is first used.
+//! Marking is slower than visiting all nodes when many nodes are marked,
+//! so passes with many marked nodes visit all nodes instead.
+
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::{visit, Index, SortOrder, Storage};
+
+/// Marking is used when fewer than one in this many nodes swap the generator in a pass.
+pub(crate) const SPARSE: usize = 256;
+
+/// Continues swapping the generator after a pass with few swaps, see `solve`.
+///
+/// The limits are the maximum passes and swaps,
+/// and `done` is the number of passes and swaps so far.
+pub(crate) fn solve<S, I, C>(
+    nodes: &mut S,
+    children: &C,
+    gen: &mut [usize],
+    order: SortOrder,
+    limits: (usize, usize),
+    done: (usize, usize),
+) -> Result<(usize, usize), usize>
+    where S: Storage + ?Sized,
+          I: Index,
+          C: Fn(&mut S::Output) -> &mut [I]
+{
+    let n = nodes.len();
+    let (max_passes, max_swaps) = limits;
+    let (mut passes, mut swaps) = done;
+    // The nodes whose visit swapped the generator in the last pass that visited all nodes.
+    let mut swapped: Vec<usize> = vec![];
+    let mut scratch = vec![];
+    // Whether to collect the nodes that swap in the next pass that visits all nodes.
+    let mut record = true;
+    // The parents and marked nodes, created when marking is first used.
+    let mut marking: Option<(Parents, Queue)> = None;
+    // Whether the queue holds the nodes to visit in the next pass.
+    let mut marked = false;
+    loop {
+        if passes == max_passes {
+            return Err(passes);
+        }
+        passes += 1;
+        match marking {
+            Some((ref parents, ref mut queue)) if marked && queue.len() * SPARSE < n => {
+                let mut changed = false;
+                queue.start_pass();
+                while let Some(i) = queue.pop() {
+                    let k = visit(i, children(&mut nodes[i]), gen, order, &mut scratch);
+                    if k > 0 {
+                        swaps = swaps.saturating_add(k);
+                        if swaps > max_swaps {
+                            return Err(passes);
+                        }
+                        changed = true;
+                        mark_around(i, children(&mut nodes[i]), parents, queue);
+                    }
+                }
+                if !changed {
+                    return Ok((passes, swaps));
+                }
+            }
+            _ => {
+                // Visit all nodes, since marking is slower than visiting
+                // when many nodes are marked.
+                let before = swaps;
+                let mut changed = false;
+                swapped.clear();
+                for i in 0..n {
+                    let k = visit(i, children(&mut nodes[i]), gen, order, &mut scratch);
+                    if k > 0 {
+                        swaps = swaps.saturating_add(k);
+                        if swaps > max_swaps {
+                            return Err(passes);
+                        }
+                        changed = true;
+                        if record {
+                            swapped.push(i);
+                        }
+                    }
+                }
+                if !changed {
+                    return Ok((passes, swaps));
+                }
+                marked = record && swapped.len() * SPARSE < n;
+                // Collect the nodes that swap in the next pass when this pass swapped few times,
+                // to avoid collecting them when marking would not be used.
+                record = (swaps - before).saturating_mul(SPARSE) < n;
+                if marked {
+                    let (parents, queue) = marking
+                        .get_or_insert_with(|| (Parents::new(nodes, children), Queue::new(n)));
+                    queue.clear();
+                    for &i in &swapped {
+                        mark_around(i, children(&mut nodes[i]), parents, queue);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Marks the nodes whose visit reads the generator at node `i` or its children.
+fn mark_around<I: Index>(i: usize, children: &[I], parents: &Parents, queue: &mut Queue) {
+    queue.mark(i);
+    for &p in parents.of(i) {
+        queue.mark(p);
+    }
+    for ch in children {
+        let ch = ch.to_usize();
+        queue.mark(ch);
+        for &p in parents.of(ch) {
+            queue.mark(p);
+        }
+    }
+}
+
+/// The nodes listing every node as a child,
+/// where the parents of node `i` are stored at `parents[offsets[i]..offsets[i + 1]]`.
+struct Parents {
+    offsets: Vec<usize>,
+    parents: Vec<usize>,
+}
+
+impl Parents {
+    fn new<S, I, C>(nodes: &mut S, children: &C) -> Parents
+        where S: Storage + ?Sized,
+              I: Index,
+              C: Fn(&mut S::Output) -> &mut [I]
+    {
+        let n = nodes.len();
+        let mut offsets = vec![0; n + 1];
+        for i in 0..n {
+            for ch in children(&mut nodes[i]).iter() {
+                offsets[ch.to_usize() + 1] += 1;
+            }
+        }
+        for i in 0..n {
+            offsets[i + 1] += offsets[i];
+        }
+        let mut parents = vec![0; offsets[n]];
+        let mut next = offsets.clone();
+        for i in 0..n {
+            for ch in children(&mut nodes[i]).iter() {
+                let ch = ch.to_usize();
+                parents[next[ch]] = i;
+                next[ch] += 1;
+            }
+        }
+        Parents {offsets, parents}
+    }
+
+    fn of(&self, i: usize) -> &[usize] {
+        &self.parents[self.offsets[i]..self.offsets[i + 1]]
+    }
+}
+
+/// The marked nodes, split into the nodes to visit in the current pass and in the next pass.
+struct Queue {
+    /// The nodes after the node being visited, which are visited in the current pass.
+    current: BinaryHeap<Reverse<usize>>,
+    /// The nodes at or before the node being visited, which are visited in the next pass.
+    next: Vec<usize>,
+    /// Whether every node is in one of the queues.
+    queued: Vec<bool>,
+    /// The node being visited.
+    cursor: Option<usize>,
+}
+
+impl Queue {
+    fn new(n: usize) -> Queue {
+        Queue {
+            current: BinaryHeap::new(),
+            next: vec![],
+            queued: vec![false; n],
+            cursor: None,
+        }
+    }
+
+    fn mark(&mut self, i: usize) {
+        if self.queued[i] {
+            return;
+        }
+        self.queued[i] = true;
+        match self.cursor {
+            Some(cursor) if i > cursor => self.current.push(Reverse(i)),
+            _ => self.next.push(i),
+        }
+    }
+
+    /// Returns the number of nodes to visit in the next pass.
+    fn len(&self) -> usize {self.next.len()}
+
+    /// Removes the nodes of the next pass.
+    fn clear(&mut self) {
+        for i in self.next.drain(..) {
+            self.queued[i] = false;
+        }
+    }
+
+    /// Moves the nodes of the next pass to the current pass.
+    fn start_pass(&mut self) {
+        self.current = self.next.drain(..).map(Reverse).collect();
+        self.cursor = None;
+    }
+
+    /// Returns the next node to visit in the current pass.
+    fn pop(&mut self) -> Option<usize> {
+        let Reverse(i) = self.current.pop()?;
+        self.queued[i] = false;
+        self.cursor = Some(i);
+        Some(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Lcg;
+
+    #[derive(Clone, PartialEq, Debug)]
+    struct Node {
+        children: Vec<usize>,
+    }
+
+    /// Solves by visiting all nodes in every pass.
+    fn solve_full(nodes: &mut [Node], gen: &mut [usize]) -> usize {
+        let mut scratch = vec![];
+        let mut passes = 0;
+        loop {
+            passes += 1;
+            let mut changed = false;
+            for (i, node) in nodes.iter().enumerate() {
+                changed |= visit(i, &node.children, gen, SortOrder::Ascending, &mut scratch) > 0;
+            }
+            if !changed {
+                return passes;
+            }
+        }
+    }
+
+    /// Builds a tree where every node `k > 0` has a random parent before it,
+    /// with `moves` pairs of nodes exchanging positions.
+    fn tree(n: usize, moves: usize, rng: &mut Lcg) -> Vec<Node> {
+        let mut pos: Vec<usize> = (0..n).collect();
+        for _ in 0..moves {
+            pos.swap(rng.next(n), rng.next(n));
+        }
+        let mut nodes: Vec<Node> = vec![Node {children: vec![]}; n];
+        for k in 1..n {
+            nodes[pos[rng.next(k)]].children.push(pos[k]);
+        }
+        nodes
+    }
+
+    #[test]
+    fn same_as_full() {
+        let mut rng = Lcg(61);
+        let mut cases: Vec<Vec<Node>> = vec![];
+        for n in 0..100 {
+            cases.push(tree(n, n, &mut rng));
+        }
+        // Few nodes out of place, where passes after the first pass use marking.
+        for &n in &[300, 1000, 3000] {
+            for moves in 1..4 {
+                cases.push(tree(n, moves, &mut rng));
+            }
+        }
+        // A chain with the leaf stored first, which moves the leaf one step per pass.
+        let n = 1000;
+        cases.push((0..n).map(|i| Node {
+            children: if i == 0 || i == n - 1 {vec![]} else {vec![i + 1]},
+        }).collect());
+        cases.last_mut().unwrap()[n - 1].children = vec![0];
+
+        for mut nodes in cases {
+            let n = nodes.len();
+            let mut expected: Vec<usize> = (0..n).collect();
+            let passes = solve_full(&mut nodes, &mut expected);
+            let mut gen: Vec<usize> = (0..n).collect();
+            let res = solve(&mut nodes, &|n: &mut Node| &mut n.children[..], &mut gen,
+                SortOrder::Ascending, (usize::MAX, usize::MAX), (0, 0));
+            assert_eq!(res.map(|(passes, _)| passes), Ok(passes));
+            assert_eq!(gen, expected);
+        }
+    }
+}